@@ -17,4 +17,16 @@ impl DocumentType {
             system_id: system_id.unwrap_or_default(),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn public_id(&self) -> &str {
+        &self.public_id
+    }
+
+    pub fn system_id(&self) -> &str {
+        &self.system_id
+    }
 }
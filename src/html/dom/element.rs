@@ -0,0 +1,74 @@
+use crate::html::tokens::Attribute;
+
+//https://infra.spec.whatwg.org/#namespaces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Namespace {
+    Html,
+    MathMl,
+    Svg,
+    XLink,
+    Xml,
+    Xmlns,
+}
+
+impl Namespace {
+    // the prefix the html5lib tree construction tests write names in the
+    // namespace with
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::MathMl => "math",
+            Self::Svg => "svg",
+            Self::XLink => "xlink",
+            Self::Xml => "xml",
+            Self::Xmlns => "xmlns",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Element {
+    name: String,
+    namespace: Namespace,
+    attributes: Vec<Attribute>,
+}
+
+impl Element {
+    pub fn new(name: String, namespace: Namespace, attributes: Vec<Attribute>) -> Self {
+        Self {
+            name,
+            namespace,
+            attributes,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    // attributes in a namespace aren't found by their local name alone
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.namespace.is_none() && attribute.name == name)
+            .map(|attribute| attribute.value.as_str())
+    }
+
+    // a second html or body start tag only adds the attributes the element
+    // doesn't already have
+    pub fn add_missing_attributes(&mut self, attributes: &[Attribute]) {
+        for attribute in attributes {
+            if self.get_attribute(&attribute.name).is_none() {
+                self.attributes.push(attribute.clone());
+            }
+        }
+    }
+}
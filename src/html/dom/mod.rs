@@ -1,23 +1,35 @@
 mod documenttype;
+mod element;
+mod quirks;
 
 pub use documenttype::DocumentType;
+pub use element::{Element, Namespace};
+pub use quirks::{QuirksMode, quirks_mode_from_doctype};
 
-use crate::html::HTMLError;
+use crate::html::{
+    encoding::{Confidence, Encoding},
+    HTMLError,
+};
 use crate::arena::{ArenaRef, Arena};
+use crate::stream::Span;
 
 #[derive(Debug)]
 pub enum DomObject {
     Document,
+    DocumentFragment,
     DocumentType(DocumentType),
-    Element(String),
-    Head,
-    Text(String)
+    Element(Element),
+    Text(String),
+    Comment(String),
 }
 
 pub struct DomTree {
     root: Option<ArenaRef>,
     doctype: Option<ArenaRef>,
     head: Option<ArenaRef>,
+    quirks_mode: QuirksMode,
+    encoding: Encoding,
+    confidence: Confidence,
     pub arena: Arena<DomNode>,
 }
 
@@ -29,6 +41,9 @@ impl DomTree {
             root: Some(root_ref),
             doctype: None,
             head: None,
+            quirks_mode: QuirksMode::NoQuirks,
+            encoding: Encoding::Utf8,
+            confidence: Confidence::Irrelevant,
             arena,
         }
     }
@@ -36,18 +51,87 @@ impl DomTree {
     pub fn insert (
             &mut self, 
             obj: DomObject, 
-            parent_ref: ArenaRef) -> Result<ArenaRef, HTMLError> {
+            parent_ref: ArenaRef,
+            span: Span) -> Result<ArenaRef, HTMLError> {
+        let child_ref = self.create(obj, span);
+        self.append(child_ref, parent_ref)?;
+        Ok(child_ref)
+    }
+
+    // a node that isn't in the tree yet. Template elements come with the
+    // document fragment that holds their contents.
+    pub fn create(&mut self, obj: DomObject, span: Span) -> ArenaRef {
+        let is_template = matches!(
+            &obj,
+            DomObject::Element(element)
+                if element.namespace() == Namespace::Html && element.name() == "template"
+        );
         let mut node = DomNode::new(obj);
-        node.parent = Some(parent_ref);
-        let child_ref : ArenaRef = self.arena.add(node);
-        if let Some(parent) = self.arena.get_mut(parent_ref) {
-            parent.children.push(child_ref);
-            Ok(child_ref)
-        } else {
-            Err(HTMLError::InaccessibleDomTreeNode)
+        node.span = span;
+        if is_template {
+            let contents = self.arena.add(DomNode::new(DomObject::DocumentFragment));
+            node.template_contents = Some(contents);
+        }
+        self.arena.add(node)
+    }
+
+    // makes the node the last child of the parent, taking it out of
+    // wherever it was before
+    pub fn append(&mut self, node_ref: ArenaRef, parent_ref: ArenaRef) -> Result<(), HTMLError> {
+        self.insert_before(node_ref, parent_ref, None)
+    }
+
+    // puts the node among the parent's children just before the sibling,
+    // or last if there's no sibling or it isn't one of the children
+    pub fn insert_before(
+            &mut self,
+            node_ref: ArenaRef,
+            parent_ref: ArenaRef,
+            sibling_ref: Option<ArenaRef>) -> Result<(), HTMLError> {
+        self.detach(node_ref)?;
+        let parent = self.arena
+            .get_mut(parent_ref)
+            .ok_or(HTMLError::InaccessibleDomTreeNode)?;
+        let idx = sibling_ref
+            .and_then(|sibling_ref| {
+                parent.children.iter().position(|&child| child == sibling_ref)
+            })
+            .unwrap_or(parent.children.len());
+        parent.children.insert(idx, node_ref);
+        if let Some(node) = self.arena.get_mut(node_ref) {
+            node.parent = Some(parent_ref);
+        }
+        Ok(())
+    }
+
+    // moves every child of one node to the end of another, keeping their
+    // order
+    pub fn move_children(&mut self, from_ref: ArenaRef, to_ref: ArenaRef) -> Result<(), HTMLError> {
+        let children = self.arena
+            .get(from_ref)
+            .ok_or(HTMLError::InaccessibleDomTreeNode)?
+            .children
+            .clone();
+        for child_ref in children {
+            self.append(child_ref, to_ref)?;
         }
+        Ok(())
     }
     
+    // takes the node out of its parent's children, it stays in the arena
+    // so it can be inserted somewhere else
+    pub fn detach(&mut self, node_ref: ArenaRef) -> Result<(), HTMLError> {
+        let parent_ref = self.arena
+            .get_mut(node_ref)
+            .ok_or(HTMLError::InaccessibleDomTreeNode)?
+            .parent
+            .take();
+        if let Some(parent) = parent_ref.and_then(|parent_ref| self.arena.get_mut(parent_ref)) {
+            parent.children.retain(|&child| child != node_ref);
+        }
+        Ok(())
+    }
+
     pub fn set_doctype(&mut self, doctype: ArenaRef) {
         self.doctype = Some(doctype);
     }
@@ -56,6 +140,44 @@ impl DomTree {
         self.head = Some(head);
     }
 
+    pub fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks_mode = mode;
+    }
+
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding, confidence: Confidence) {
+        self.encoding = encoding;
+        self.confidence = confidence;
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn confidence(&self) -> Confidence {
+        self.confidence
+    }
+
+    pub fn get_root(&self) -> Option<ArenaRef> {
+        self.root
+    }
+
+    pub fn get_head(&self) -> Option<ArenaRef> {
+        self.head
+    }
+
+    pub fn get_parent_of(&self, node_ref: ArenaRef) -> Option<ArenaRef> {
+        self.arena.get(node_ref)?.parent
+    }
+
+    //https://html.spec.whatwg.org/multipage/scripting.html#template-contents
+    pub fn get_template_contents(&self, node_ref: ArenaRef) -> Option<ArenaRef> {
+        self.arena.get(node_ref)?.template_contents
+    }
+
     pub fn get_last_child_of(&mut self, node_ref: ArenaRef) -> &mut DomObject {
         let node = self.arena.get(node_ref).unwrap();
         let child_ref = node.children.last().unwrap();
@@ -64,10 +186,83 @@ impl DomTree {
     }
 }
 
+impl DomTree {
+    // renders the tree one node per line, indented by depth, in the same
+    // spirit as the html5lib tree construction test format
+    pub fn present(&self) -> String {
+        let mut fmt_str = String::new();
+        if let Some(root) = self.root {
+            if let Some(node) = self.arena.get(root) {
+                for child in node.children.iter() {
+                    self.present_node(*child, 0, &mut fmt_str);
+                }
+            }
+        }
+        fmt_str
+    }
+
+    fn present_node(&self, node_ref: ArenaRef, depth: usize, fmt_str: &mut String) {
+        if let Some(node) = self.arena.get(node_ref) {
+            fmt_str.push('|');
+            fmt_str.push_str(&" ".repeat(depth * 2 + 1));
+            match &node.dom_obj {
+                DomObject::Document => fmt_str.push_str("#document"),
+                DomObject::DocumentFragment => fmt_str.push_str("content"),
+                DomObject::DocumentType(doctype) => {
+                    fmt_str.push_str(&format!("<!DOCTYPE {}", doctype.name()));
+                    if !doctype.public_id().is_empty() || !doctype.system_id().is_empty() {
+                        fmt_str.push_str(&format!(
+                            " \"{}\" \"{}\"",
+                            doctype.public_id(),
+                            doctype.system_id()
+                        ));
+                    }
+                    fmt_str.push('>');
+                },
+                DomObject::Element(element) => {
+                    match element.namespace() {
+                        Namespace::Html => fmt_str.push_str(&format!("<{}>", element.name())),
+                        namespace => fmt_str.push_str(&format!(
+                            "<{} {}>",
+                            namespace.short_name(),
+                            element.name()
+                        )),
+                    }
+                },
+                DomObject::Text(data) => fmt_str.push_str(&format!("\"{}\"", data)),
+                DomObject::Comment(data) => fmt_str.push_str(&format!("<!-- {} -->", data)),
+            }
+            fmt_str.push('\n');
+            if let DomObject::Element(element) = &node.dom_obj {
+                for attribute in element.attributes() {
+                    fmt_str.push('|');
+                    fmt_str.push_str(&" ".repeat(depth * 2 + 3));
+                    if let Some(namespace) = attribute.namespace {
+                        fmt_str.push_str(namespace.short_name());
+                        fmt_str.push(' ');
+                    }
+                    fmt_str.push_str(&format!("{}=\"{}\"\n", attribute.name, attribute.value));
+                }
+            }
+            if let Some(contents) = node.template_contents {
+                self.present_node(contents, depth + 1, fmt_str);
+            }
+            for child in node.children.iter() {
+                self.present_node(*child, depth + 1, fmt_str);
+            }
+        }
+    }
+}
+
 pub struct DomNode {
     parent: Option<ArenaRef>,
     pub children: Vec<ArenaRef>,
-    pub dom_obj: DomObject
+    pub dom_obj: DomObject,
+    // the source of the token the node was created for; elements cover
+    // their start tag, text covers every run merged into it, and nodes the
+    // parser implies get an empty span where they were implied
+    pub span: Span,
+    template_contents: Option<ArenaRef>,
 }
 
 impl DomNode {
@@ -75,7 +270,9 @@ impl DomNode {
         Self {
             parent: None,
             children: Vec::new(),
-            dom_obj: obj
+            dom_obj: obj,
+            span: Span::default(),
+            template_contents: None,
         }
     }
 }
@@ -0,0 +1,178 @@
+//https://dom.spec.whatwg.org/#concept-document-quirks
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+const QUIRKS_PUBLIC_IDS: [&str; 3] = [
+    "-//w3o//dtd w3 html strict 3.0//en//",
+    "-/w3c/dtd html 4.0 transitional/en",
+    "html",
+];
+
+const QUIRKS_SYSTEM_ID: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+
+const QUIRKS_PUBLIC_ID_PREFIXES: [&str; 55] = [
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19970916::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+// quirks when the system identifier is missing, limited quirks otherwise
+const HTML4_PUBLIC_ID_PREFIXES: [&str; 2] = [
+    "-//w3c//dtd html 4.01 frameset//",
+    "-//w3c//dtd html 4.01 transitional//",
+];
+
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: [&str; 2] = [
+    "-//w3c//dtd xhtml 1.0 frameset//",
+    "-//w3c//dtd xhtml 1.0 transitional//",
+];
+
+//https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+pub fn quirks_mode_from_doctype(
+    name: &str,
+    public_id: Option<&str>,
+    system_id: Option<&str>,
+    force_quirks: bool
+    ) -> QuirksMode {
+    let public_id = public_id.map(|id| id.to_ascii_lowercase());
+    let system_id = system_id.map(|id| id.to_ascii_lowercase());
+    let public_starts_with = |prefixes: &[&str]| {
+        match &public_id {
+            Some(id) => prefixes.iter().any(|prefix| id.starts_with(prefix)),
+            None => false,
+        }
+    };
+
+    if force_quirks
+        || name != "html"
+        || public_id.as_deref().is_some_and(|id| QUIRKS_PUBLIC_IDS.contains(&id))
+        || system_id.as_deref() == Some(QUIRKS_SYSTEM_ID)
+        || public_starts_with(&QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_none() && public_starts_with(&HTML4_PUBLIC_ID_PREFIXES)) {
+        QuirksMode::Quirks
+    } else if public_starts_with(&LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_some() && public_starts_with(&HTML4_PUBLIC_ID_PREFIXES)) {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(public_id: Option<&str>, system_id: Option<&str>) -> QuirksMode {
+        quirks_mode_from_doctype("html", public_id, system_id, false)
+    }
+
+    #[test]
+    fn html5_doctype() {
+        assert_eq!(mode(None, None), QuirksMode::NoQuirks);
+        assert_eq!(mode(None, Some("about:legacy-compat")), QuirksMode::NoQuirks);
+        assert_eq!(
+            mode(Some("-//W3C//DTD HTML 4.01//EN"), Some("http://www.w3.org/TR/html4/strict.dtd")),
+            QuirksMode::NoQuirks);
+    }
+
+    #[test]
+    fn forced_or_other_names() {
+        assert_eq!(quirks_mode_from_doctype("html", None, None, true), QuirksMode::Quirks);
+        assert_eq!(quirks_mode_from_doctype("svg", None, None, false), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn quirks_public_identifiers() {
+        assert_eq!(mode(Some("HTML"), None), QuirksMode::Quirks);
+        assert_eq!(mode(Some("-//W3O//DTD W3 HTML Strict 3.0//EN//"), None), QuirksMode::Quirks);
+        // the exact matches aren't prefixes
+        assert_eq!(mode(Some("html5"), None), QuirksMode::NoQuirks);
+        assert_eq!(mode(Some("-//IETF//DTD HTML 2.0//EN"), None), QuirksMode::Quirks);
+        assert_eq!(
+            mode(Some("-//WebTechs//DTD Mozilla HTML//EN"), Some("x")),
+            QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn quirks_system_identifier() {
+        assert_eq!(
+            mode(None, Some("HTTP://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")),
+            QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn html4_depends_on_system_identifier() {
+        let public_id = Some("-//W3C//DTD HTML 4.01 Transitional//EN");
+        assert_eq!(mode(public_id, None), QuirksMode::Quirks);
+        assert_eq!(
+            mode(public_id, Some("http://www.w3.org/TR/html4/loose.dtd")),
+            QuirksMode::LimitedQuirks);
+    }
+
+    #[test]
+    fn limited_quirks() {
+        assert_eq!(
+            mode(Some("-//W3C//DTD XHTML 1.0 Transitional//EN"), None),
+            QuirksMode::LimitedQuirks);
+        assert_eq!(
+            mode(Some("-//W3C//DTD XHTML 1.0 Frameset//EN"), Some("x")),
+            QuirksMode::LimitedQuirks);
+    }
+}
@@ -0,0 +1,824 @@
+mod prescan;
+mod tables;
+
+pub use prescan::{prescan, extract_from_content};
+
+// how many bytes sniffing waits for before deciding on an encoding
+pub const SNIFF_LEN: usize = 1024;
+
+//https://encoding.spec.whatwg.org/#names-and-labels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Ibm866,
+    Iso8859_2,
+    Iso8859_3,
+    Iso8859_4,
+    Iso8859_5,
+    Iso8859_6,
+    Iso8859_7,
+    Iso8859_8,
+    Iso8859_8I,
+    Iso8859_10,
+    Iso8859_13,
+    Iso8859_14,
+    Iso8859_15,
+    Iso8859_16,
+    Koi8R,
+    Koi8U,
+    Macintosh,
+    Windows874,
+    Windows1250,
+    Windows1251,
+    Windows1252,
+    Windows1253,
+    Windows1254,
+    Windows1255,
+    Windows1256,
+    Windows1257,
+    Windows1258,
+    XMacCyrillic,
+    Gbk,
+    Gb18030,
+    Big5,
+    EucJp,
+    ShiftJis,
+    EucKr,
+    Utf16Be,
+    Utf16Le,
+    XUserDefined,
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#concept-encoding-confidence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Confidence {
+    Tentative,
+    Certain,
+    Irrelevant,
+}
+
+impl Encoding {
+    //https://encoding.spec.whatwg.org/#concept-encoding-get
+    pub fn from_label(label: &str) -> Option<Self> {
+        let label = label
+            .trim_matches(|char| matches!(char, '\t' | '\n' | '\u{000C}' | '\r' | ' '))
+            .to_ascii_lowercase();
+        let encoding = match label.as_str() {
+            "unicode-1-1-utf-8" | "unicode11utf8" | "unicode20utf8" | "utf-8" |
+            "utf8" | "x-unicode20utf8" => Self::Utf8,
+            "866" | "cp866" | "csibm866" | "ibm866" => Self::Ibm866,
+            "csisolatin2" | "iso-8859-2" | "iso-ir-101" | "iso8859-2" | "iso88592" |
+            "iso_8859-2" | "iso_8859-2:1987" | "l2" | "latin2" => Self::Iso8859_2,
+            "csisolatin3" | "iso-8859-3" | "iso-ir-109" | "iso8859-3" | "iso88593" |
+            "iso_8859-3" | "iso_8859-3:1988" | "l3" | "latin3" => Self::Iso8859_3,
+            "csisolatin4" | "iso-8859-4" | "iso-ir-110" | "iso8859-4" | "iso88594" |
+            "iso_8859-4" | "iso_8859-4:1988" | "l4" | "latin4" => Self::Iso8859_4,
+            "csisolatincyrillic" | "cyrillic" | "iso-8859-5" | "iso-ir-144" |
+            "iso8859-5" | "iso88595" | "iso_8859-5" | "iso_8859-5:1988" => Self::Iso8859_5,
+            "arabic" | "asmo-708" | "csiso88596e" | "csiso88596i" | "csisolatinarabic" |
+            "ecma-114" | "iso-8859-6" | "iso-8859-6-e" | "iso-8859-6-i" | "iso-ir-127" |
+            "iso8859-6" | "iso88596" | "iso_8859-6" | "iso_8859-6:1987" => Self::Iso8859_6,
+            "csisolatingreek" | "ecma-118" | "elot_928" | "greek" | "greek8" |
+            "iso-8859-7" | "iso-ir-126" | "iso8859-7" | "iso88597" | "iso_8859-7" |
+            "iso_8859-7:1987" | "sun_eu_greek" => Self::Iso8859_7,
+            "csiso88598e" | "csisolatinhebrew" | "hebrew" | "iso-8859-8" |
+            "iso-8859-8-e" | "iso-ir-138" | "iso8859-8" | "iso88598" | "iso_8859-8" |
+            "iso_8859-8:1988" | "visual" => Self::Iso8859_8,
+            "csiso88598i" | "iso-8859-8-i" | "logical" => Self::Iso8859_8I,
+            "csisolatin6" | "iso-8859-10" | "iso-ir-157" | "iso8859-10" | "iso885910" |
+            "l6" | "latin6" => Self::Iso8859_10,
+            "iso-8859-13" | "iso8859-13" | "iso885913" => Self::Iso8859_13,
+            "iso-8859-14" | "iso8859-14" | "iso885914" => Self::Iso8859_14,
+            "csisolatin9" | "iso-8859-15" | "iso8859-15" | "iso885915" | "iso_8859-15" |
+            "l9" => Self::Iso8859_15,
+            "iso-8859-16" => Self::Iso8859_16,
+            "cskoi8r" | "koi" | "koi8" | "koi8-r" | "koi8_r" => Self::Koi8R,
+            "koi8-ru" | "koi8-u" => Self::Koi8U,
+            "csmacintosh" | "mac" | "macintosh" | "x-mac-roman" => Self::Macintosh,
+            "dos-874" | "iso-8859-11" | "iso8859-11" | "iso885911" | "tis-620" |
+            "windows-874" => Self::Windows874,
+            "cp1250" | "windows-1250" | "x-cp1250" => Self::Windows1250,
+            "cp1251" | "windows-1251" | "x-cp1251" => Self::Windows1251,
+            "ansi_x3.4-1968" | "ascii" | "cp1252" | "cp819" | "csisolatin1" | "ibm819" |
+            "iso-8859-1" | "iso-ir-100" | "iso8859-1" | "iso88591" | "iso_8859-1" |
+            "iso_8859-1:1987" | "l1" | "latin1" | "us-ascii" | "windows-1252" |
+            "x-cp1252" => Self::Windows1252,
+            "cp1253" | "windows-1253" | "x-cp1253" => Self::Windows1253,
+            "cp1254" | "csisolatin5" | "iso-8859-9" | "iso-ir-148" | "iso8859-9" |
+            "iso88599" | "iso_8859-9" | "iso_8859-9:1989" | "l5" | "latin5" |
+            "windows-1254" | "x-cp1254" => Self::Windows1254,
+            "cp1255" | "windows-1255" | "x-cp1255" => Self::Windows1255,
+            "cp1256" | "windows-1256" | "x-cp1256" => Self::Windows1256,
+            "cp1257" | "windows-1257" | "x-cp1257" => Self::Windows1257,
+            "cp1258" | "windows-1258" | "x-cp1258" => Self::Windows1258,
+            "x-mac-cyrillic" | "x-mac-ukrainian" => Self::XMacCyrillic,
+            "chinese" | "csgb2312" | "csiso58gb231280" | "gb2312" | "gb_2312" |
+            "gb_2312-80" | "gbk" | "iso-ir-58" | "x-gbk" => Self::Gbk,
+            "gb18030" => Self::Gb18030,
+            "big5" | "big5-hkscs" | "cn-big5" | "csbig5" | "x-x-big5" => Self::Big5,
+            "cseucpkdfmtjapanese" | "euc-jp" | "x-euc-jp" => Self::EucJp,
+            "csshiftjis" | "ms932" | "ms_kanji" | "shift-jis" | "shift_jis" | "sjis" |
+            "windows-31j" | "x-sjis" => Self::ShiftJis,
+            "cseuckr" | "csksc56011987" | "euc-kr" | "iso-ir-149" | "korean" |
+            "ks_c_5601-1987" | "ks_c_5601-1989" | "ksc5601" | "ksc_5601" |
+            "windows-949" => Self::EucKr,
+            "unicodefffe" | "utf-16be" => Self::Utf16Be,
+            "csunicode" | "iso-10646-ucs-2" | "ucs-2" | "unicode" | "unicodefeff" |
+            "utf-16" | "utf-16le" => Self::Utf16Le,
+            "x-user-defined" => Self::XUserDefined,
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "UTF-8",
+            Self::Ibm866 => "IBM866",
+            Self::Iso8859_2 => "ISO-8859-2",
+            Self::Iso8859_3 => "ISO-8859-3",
+            Self::Iso8859_4 => "ISO-8859-4",
+            Self::Iso8859_5 => "ISO-8859-5",
+            Self::Iso8859_6 => "ISO-8859-6",
+            Self::Iso8859_7 => "ISO-8859-7",
+            Self::Iso8859_8 => "ISO-8859-8",
+            Self::Iso8859_8I => "ISO-8859-8-I",
+            Self::Iso8859_10 => "ISO-8859-10",
+            Self::Iso8859_13 => "ISO-8859-13",
+            Self::Iso8859_14 => "ISO-8859-14",
+            Self::Iso8859_15 => "ISO-8859-15",
+            Self::Iso8859_16 => "ISO-8859-16",
+            Self::Koi8R => "KOI8-R",
+            Self::Koi8U => "KOI8-U",
+            Self::Macintosh => "macintosh",
+            Self::Windows874 => "windows-874",
+            Self::Windows1250 => "windows-1250",
+            Self::Windows1251 => "windows-1251",
+            Self::Windows1252 => "windows-1252",
+            Self::Windows1253 => "windows-1253",
+            Self::Windows1254 => "windows-1254",
+            Self::Windows1255 => "windows-1255",
+            Self::Windows1256 => "windows-1256",
+            Self::Windows1257 => "windows-1257",
+            Self::Windows1258 => "windows-1258",
+            Self::XMacCyrillic => "x-mac-cyrillic",
+            Self::Gbk => "GBK",
+            Self::Gb18030 => "gb18030",
+            Self::Big5 => "Big5",
+            Self::EucJp => "EUC-JP",
+            Self::ShiftJis => "Shift_JIS",
+            Self::EucKr => "EUC-KR",
+            Self::Utf16Be => "UTF-16BE",
+            Self::Utf16Le => "UTF-16LE",
+            Self::XUserDefined => "x-user-defined",
+        }
+    }
+
+    fn single_byte_table(&self) -> Option<&'static [u16; 128]> {
+        let table = match self {
+            Self::Ibm866 => &tables::IBM866,
+            Self::Iso8859_2 => &tables::ISO_8859_2,
+            Self::Iso8859_3 => &tables::ISO_8859_3,
+            Self::Iso8859_4 => &tables::ISO_8859_4,
+            Self::Iso8859_5 => &tables::ISO_8859_5,
+            Self::Iso8859_6 => &tables::ISO_8859_6,
+            Self::Iso8859_7 => &tables::ISO_8859_7,
+            Self::Iso8859_8 |
+            Self::Iso8859_8I => &tables::ISO_8859_8,
+            Self::Iso8859_10 => &tables::ISO_8859_10,
+            Self::Iso8859_13 => &tables::ISO_8859_13,
+            Self::Iso8859_14 => &tables::ISO_8859_14,
+            Self::Iso8859_15 => &tables::ISO_8859_15,
+            Self::Iso8859_16 => &tables::ISO_8859_16,
+            Self::Koi8R => &tables::KOI8_R,
+            Self::Koi8U => &tables::KOI8_U,
+            Self::Macintosh => &tables::MACINTOSH,
+            Self::Windows874 => &tables::WINDOWS_874,
+            Self::Windows1250 => &tables::WINDOWS_1250,
+            Self::Windows1251 => &tables::WINDOWS_1251,
+            Self::Windows1252 => &tables::WINDOWS_1252,
+            Self::Windows1253 => &tables::WINDOWS_1253,
+            Self::Windows1254 => &tables::WINDOWS_1254,
+            Self::Windows1255 => &tables::WINDOWS_1255,
+            Self::Windows1256 => &tables::WINDOWS_1256,
+            Self::Windows1257 => &tables::WINDOWS_1257,
+            Self::Windows1258 => &tables::WINDOWS_1258,
+            Self::XMacCyrillic => &tables::X_MAC_CYRILLIC,
+            _ => return None,
+        };
+        Some(table)
+    }
+
+    //https://encoding.spec.whatwg.org/#decode
+    // a BOM for the encoding itself is dropped, malformed input decodes to
+    // U+FFFD
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        Decoder::new(*self).decode(bytes, true)
+    }
+
+    // decodes as much of bytes as makes up whole characters, unless this is
+    // the last of the input. Returns how many bytes were used.
+    fn decode_prefix(&self, bytes: &[u8], last: bool) -> (String, usize) {
+        if let Some(table) = self.single_byte_table() {
+            return (decode_single_byte(bytes, table), bytes.len());
+        }
+        match self {
+            Self::Utf8 => decode_utf8(bytes, last),
+            Self::Utf16Be => decode_utf16(bytes, last, u16::from_be_bytes),
+            Self::Utf16Le => decode_utf16(bytes, last, u16::from_le_bytes),
+            // GBK is decoded as gb18030, only encoding them differs
+            Self::Gbk |
+            Self::Gb18030 => decode_gb18030(bytes, last),
+            Self::Big5 => decode_big5(bytes, last),
+            Self::ShiftJis => decode_shift_jis(bytes, last),
+            Self::EucJp => decode_euc_jp(bytes, last),
+            Self::EucKr => decode_euc_kr(bytes, last),
+            Self::XUserDefined => {
+                let decoded = bytes
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x00..=0x7F => byte as char,
+                        _ => char::from_u32(0xF780 + byte as u32 - 0x80).unwrap_or('\u{FFFD}'),
+                    })
+                    .collect();
+                (decoded, bytes.len())
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn bom(&self) -> &'static [u8] {
+        match self {
+            Self::Utf8 => b"\xEF\xBB\xBF",
+            Self::Utf16Be => b"\xFE\xFF",
+            Self::Utf16Le => b"\xFF\xFE",
+            _ => b"",
+        }
+    }
+}
+
+// decodes input that arrives in chunks. A chunk can end partway through a
+// character, those bytes are held back until the rest of it arrives.
+pub struct Decoder {
+    encoding: Encoding,
+    pending: Vec<u8>,
+    bom_checked: bool,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            pending: Vec::new(),
+            bom_checked: false,
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    // last says no more bytes are coming, so nothing is held back
+    pub fn decode(&mut self, bytes: &[u8], last: bool) -> String {
+        self.pending.extend_from_slice(bytes);
+        if !self.bom_checked {
+            let bom = self.encoding.bom();
+            if !last && self.pending.len() < bom.len() && bom.starts_with(&self.pending) {
+                return String::new();
+            }
+            if self.pending.starts_with(bom) {
+                self.pending.drain(..bom.len());
+            }
+            self.bom_checked = true;
+        }
+        let (decoded, used) = self.encoding.decode_prefix(&self.pending, last);
+        self.pending.drain(..used);
+        decoded
+    }
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#encoding-sniffing-algorithm
+// the transport layer's label wins over anything in the document apart from
+// a BOM. With neither, the prescan is tried and then, as the spec allows,
+// input that is valid UTF-8 is taken to be UTF-8 before falling back to
+// windows-1252. Only the first 1024 bytes are looked at, so the answer
+// doesn't depend on how much of the input has arrived.
+pub fn sniff(bytes: &[u8], transport_label: Option<&str>) -> (Encoding, Confidence) {
+    let bytes = &bytes[..usize::min(bytes.len(), SNIFF_LEN)];
+    if let Some(encoding) = bom_sniff(bytes) {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = transport_label.and_then(Encoding::from_label) {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = prescan(bytes) {
+        return (encoding, Confidence::Tentative);
+    }
+    // the cut off can fall in the middle of a character, but input that
+    // ends partway through one isn't valid
+    let is_utf8 = match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none() && bytes.len() >= SNIFF_LEN,
+    };
+    if !bytes.is_ascii() && is_utf8 {
+        return (Encoding::Utf8, Confidence::Tentative);
+    }
+    (Encoding::Windows1252, Confidence::Tentative)
+}
+
+//https://encoding.spec.whatwg.org/#bom-sniff
+fn bom_sniff(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        Some(Encoding::Utf8)
+    } else if bytes.starts_with(b"\xFE\xFF") {
+        Some(Encoding::Utf16Be)
+    } else if bytes.starts_with(b"\xFF\xFE") {
+        Some(Encoding::Utf16Le)
+    } else {
+        None
+    }
+}
+
+fn decode_single_byte(bytes: &[u8], table: &[u16; 128]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            0x00..=0x7F => byte as char,
+            _ => match table[byte as usize - 0x80] {
+                0 => '\u{FFFD}',
+                code => char::from_u32(code as u32).unwrap_or('\u{FFFD}'),
+            },
+        })
+        .collect()
+}
+
+fn decode_utf8(bytes: &[u8], last: bool) -> (String, usize) {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                decoded.push_str(valid);
+                return (decoded, bytes.len());
+            },
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                decoded.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                match error.error_len() {
+                    Some(len) => {
+                        decoded.push('\u{FFFD}');
+                        rest = &invalid[len..];
+                    },
+                    // the bytes end partway through a sequence
+                    None if !last => return (decoded, bytes.len() - invalid.len()),
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        return (decoded, bytes.len());
+                    },
+                }
+            },
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], last: bool, from_bytes: fn([u8; 2]) -> u16) -> (String, usize) {
+    let mut units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .collect();
+    let mut used = units.len() * 2;
+    // a lead surrogate may be paired with the first unit of the next chunk
+    if !last && units.last().is_some_and(|unit| (0xD800..0xDC00).contains(unit)) {
+        units.pop();
+        used -= 2;
+    }
+    let mut decoded: String = char::decode_utf16(units)
+        .map(|unit| unit.unwrap_or('\u{FFFD}'))
+        .collect();
+    // a trailing odd byte is half a code unit
+    if last && bytes.len() % 2 == 1 {
+        decoded.push('\u{FFFD}');
+        used += 1;
+    }
+    (decoded, used)
+}
+
+// the code point for pointer in one of the multi-byte indexes
+fn lookup<T: Copy + Into<u32>>(index: &[T], pointer: usize) -> Option<char> {
+    match index.get(pointer).map(|&code| code.into()) {
+        Some(0) | None => None,
+        Some(code) => char::from_u32(code),
+    }
+}
+
+fn jis0208(pointer: usize) -> Option<char> {
+    lookup(&tables::JIS0208, pointer)
+}
+
+//https://encoding.spec.whatwg.org/#shift_jis-decoder
+fn decode_shift_jis(bytes: &[u8], last: bool) -> (String, usize) {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        idx += 1;
+        match byte {
+            0x00..=0x80 => decoded.push(byte as char),
+            0xA1..=0xDF => decoded.push(
+                char::from_u32(0xFF61 + byte as u32 - 0xA1).unwrap_or('\u{FFFD}')
+            ),
+            0x81..=0x9F | 0xE0..=0xFC => {
+                let trail = match bytes.get(idx) {
+                    Some(&trail) => trail,
+                    None if !last => return (decoded, idx - 1),
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        break;
+                    },
+                };
+                let lead_offset = if byte < 0xA0 { 0x81 } else { 0xC1 };
+                let trail_offset = if trail < 0x7F { 0x40 } else { 0x41 };
+                let pointer = match trail {
+                    0x40..=0x7E | 0x80..=0xFC => Some(
+                        (byte - lead_offset) as usize * 188 + (trail - trail_offset) as usize
+                    ),
+                    _ => None,
+                };
+                match pointer.and_then(jis0208) {
+                    Some(char) => {
+                        decoded.push(char);
+                        idx += 1;
+                    },
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        // an ASCII trail byte is decoded on its own
+                        if !trail.is_ascii() {
+                            idx += 1;
+                        }
+                    },
+                }
+            },
+            _ => decoded.push('\u{FFFD}'),
+        }
+    }
+    (decoded, bytes.len())
+}
+
+//https://encoding.spec.whatwg.org/#euc-jp-decoder
+fn decode_euc_jp(bytes: &[u8], last: bool) -> (String, usize) {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let start = idx;
+        let mut byte = bytes[idx];
+        idx += 1;
+        match byte {
+            0x00..=0x7F => decoded.push(byte as char),
+            0x8E | 0x8F | 0xA1..=0xFE => {
+                let mut trail = match bytes.get(idx) {
+                    Some(&trail) => trail,
+                    None if !last => return (decoded, start),
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        break;
+                    },
+                };
+                // JIS X 0212 code points take a third byte
+                let jis0212 = byte == 0x8F && (0xA1..=0xFE).contains(&trail);
+                if jis0212 {
+                    idx += 1;
+                    byte = trail;
+                    trail = match bytes.get(idx) {
+                        Some(&trail) => trail,
+                        None if !last => return (decoded, start),
+                        None => {
+                            decoded.push('\u{FFFD}');
+                            break;
+                        },
+                    };
+                }
+                let char = match (byte, trail) {
+                    (0x8E, 0xA1..=0xDF) => char::from_u32(0xFF61 + trail as u32 - 0xA1),
+                    (0xA1..=0xFE, 0xA1..=0xFE) => {
+                        let pointer = (byte - 0xA1) as usize * 94 + (trail - 0xA1) as usize;
+                        if jis0212 {
+                            lookup(&tables::JIS0212, pointer)
+                        } else {
+                            jis0208(pointer)
+                        }
+                    },
+                    _ => None,
+                };
+                decoded.push(char.unwrap_or('\u{FFFD}'));
+                if char.is_some() || !trail.is_ascii() {
+                    idx += 1;
+                }
+            },
+            _ => decoded.push('\u{FFFD}'),
+        }
+    }
+    (decoded, bytes.len())
+}
+
+//https://encoding.spec.whatwg.org/#gb18030-decoder
+fn decode_gb18030(bytes: &[u8], last: bool) -> (String, usize) {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let start = idx;
+        let byte = bytes[idx];
+        idx += 1;
+        match byte {
+            0x00..=0x7F => decoded.push(byte as char),
+            0x80 => decoded.push('\u{20AC}'),
+            0x81..=0xFE => {
+                // the rest of the sequence, as far as it goes
+                let rest = &bytes[idx..usize::min(idx + 3, bytes.len())];
+                let four_bytes = rest.first().is_some_and(|trail| (0x30..=0x39).contains(trail));
+                let wanted = if four_bytes { 3 } else { 1 };
+                if rest.len() < wanted && !last {
+                    return (decoded, start);
+                }
+                if four_bytes {
+                    match rest {
+                        [second, third @ 0x81..=0xFE, fourth @ 0x30..=0x39] => {
+                            let pointer = (
+                                ((byte - 0x81) as usize * 10 + (second - 0x30) as usize) * 126 +
+                                (third - 0x81) as usize
+                            ) * 10 + (fourth - 0x30) as usize;
+                            decoded.push(gb18030_ranges(pointer).unwrap_or('\u{FFFD}'));
+                            idx += 3;
+                        },
+                        // the end of the input is in the middle of it
+                        [_] | [_, 0x81..=0xFE] => {
+                            decoded.push('\u{FFFD}');
+                            break;
+                        },
+                        // anything from the second byte on is decoded again
+                        _ => decoded.push('\u{FFFD}'),
+                    }
+                    continue;
+                }
+                let trail = match rest.first() {
+                    Some(&trail) => trail,
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        break;
+                    },
+                };
+                let trail_offset = if trail < 0x7F { 0x40 } else { 0x41 };
+                let char = match trail {
+                    0x40..=0x7E | 0x80..=0xFE => lookup(
+                        &tables::GB18030,
+                        (byte - 0x81) as usize * 190 + (trail - trail_offset) as usize
+                    ),
+                    _ => None,
+                };
+                decoded.push(char.unwrap_or('\u{FFFD}'));
+                if char.is_some() || !trail.is_ascii() {
+                    idx += 1;
+                }
+            },
+            _ => decoded.push('\u{FFFD}'),
+        }
+    }
+    (decoded, bytes.len())
+}
+
+//https://encoding.spec.whatwg.org/#index-gb18030-ranges-code-point
+fn gb18030_ranges(pointer: usize) -> Option<char> {
+    if (39420..189000).contains(&pointer) || pointer > 1237575 {
+        return None;
+    }
+    if pointer >= 189000 {
+        return char::from_u32((0x10000 + pointer - 189000) as u32);
+    }
+    if pointer == 7457 {
+        return Some('\u{E7C7}');
+    }
+    let idx = tables::GB18030_RANGES.partition_point(|&(start, _)| start as usize <= pointer);
+    let (start, code) = tables::GB18030_RANGES[idx - 1];
+    char::from_u32(code + (pointer as u32 - start))
+}
+
+//https://encoding.spec.whatwg.org/#big5-decoder
+fn decode_big5(bytes: &[u8], last: bool) -> (String, usize) {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        idx += 1;
+        match byte {
+            0x00..=0x7F => decoded.push(byte as char),
+            0x81..=0xFE => {
+                let trail = match bytes.get(idx) {
+                    Some(&trail) => trail,
+                    None if !last => return (decoded, idx - 1),
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        break;
+                    },
+                };
+                let trail_offset = if trail < 0x7F { 0x40 } else { 0x62 };
+                let pointer = match trail {
+                    0x40..=0x7E | 0xA1..=0xFE => Some(
+                        (byte - 0x81) as usize * 157 + (trail - trail_offset) as usize
+                    ),
+                    _ => None,
+                };
+                // a few pointers are a pair of code points
+                let pair = match pointer {
+                    Some(1133) => Some("\u{00CA}\u{0304}"),
+                    Some(1135) => Some("\u{00CA}\u{030C}"),
+                    Some(1164) => Some("\u{00EA}\u{0304}"),
+                    Some(1166) => Some("\u{00EA}\u{030C}"),
+                    _ => None,
+                };
+                if let Some(pair) = pair {
+                    decoded.push_str(pair);
+                    idx += 1;
+                    continue;
+                }
+                let char = pointer.and_then(|pointer| lookup(&tables::BIG5, pointer));
+                decoded.push(char.unwrap_or('\u{FFFD}'));
+                if char.is_some() || !trail.is_ascii() {
+                    idx += 1;
+                }
+            },
+            _ => decoded.push('\u{FFFD}'),
+        }
+    }
+    (decoded, bytes.len())
+}
+
+//https://encoding.spec.whatwg.org/#euc-kr-decoder
+fn decode_euc_kr(bytes: &[u8], last: bool) -> (String, usize) {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        idx += 1;
+        match byte {
+            0x00..=0x7F => decoded.push(byte as char),
+            0x81..=0xFE => {
+                let trail = match bytes.get(idx) {
+                    Some(&trail) => trail,
+                    None if !last => return (decoded, idx - 1),
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        break;
+                    },
+                };
+                let char = match trail {
+                    0x41..=0xFE => lookup(
+                        &tables::EUC_KR,
+                        (byte - 0x81) as usize * 190 + (trail - 0x41) as usize
+                    ),
+                    _ => None,
+                };
+                decoded.push(char.unwrap_or('\u{FFFD}'));
+                if char.is_some() || !trail.is_ascii() {
+                    idx += 1;
+                }
+            },
+            _ => decoded.push('\u{FFFD}'),
+        }
+    }
+    (decoded, bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // decodes bytes one at a time, as if each arrived in its own chunk
+    fn decode_bytewise(encoding: Encoding, bytes: &[u8]) -> String {
+        let mut decoder = Decoder::new(encoding);
+        let mut decoded = String::new();
+        for byte in bytes {
+            decoded.push_str(&decoder.decode(&[*byte], false));
+        }
+        decoded.push_str(&decoder.decode(&[], true));
+        decoded
+    }
+
+    fn assert_decodes(encoding: Encoding, bytes: &[u8], expected: &str) {
+        assert_eq!(encoding.decode(bytes), expected);
+        assert_eq!(decode_bytewise(encoding, bytes), expected);
+    }
+
+    #[test]
+    fn bom_wins_over_everything() {
+        assert_eq!(
+            sniff(b"\xEF\xBB\xBF<meta charset=koi8-r>", Some("euc-kr")),
+            (Encoding::Utf8, Confidence::Certain));
+        assert_eq!(sniff(b"\xFE\xFF\x00<", None), (Encoding::Utf16Be, Confidence::Certain));
+        assert_eq!(sniff(b"\xFF\xFE<\x00", None), (Encoding::Utf16Le, Confidence::Certain));
+    }
+
+    #[test]
+    fn bom_is_dropped() {
+        assert_decodes(Encoding::Utf8, b"\xEF\xBB\xBFa\xEF\xBB\xBF", "a\u{FEFF}");
+        assert_decodes(Encoding::Utf16Le, b"\xFF\xFEa\x00", "a");
+        assert_decodes(Encoding::Windows1252, b"\xEF\xBB\xBF", "\u{EF}\u{BB}\u{BF}");
+    }
+
+    #[test]
+    fn transport_hint_wins_over_prescan() {
+        assert_eq!(
+            sniff(b"<meta charset=koi8-r>", Some("euc-kr")),
+            (Encoding::EucKr, Confidence::Certain));
+        assert_eq!(
+            sniff(b"<meta charset=koi8-r>", Some("bogus")),
+            (Encoding::Koi8R, Confidence::Tentative));
+    }
+
+    #[test]
+    fn prescan_finds_meta() {
+        assert_eq!(prescan(b"<!-- <meta charset=koi8-r> --><meta charset=big5>"), Some(Encoding::Big5));
+        assert_eq!(
+            prescan(b"<meta http-equiv=Content-Type content='text/html; charset=gbk'>"),
+            Some(Encoding::Gbk));
+        assert_eq!(prescan(b"<meta content='text/html; charset=gbk'>"), None);
+        assert_eq!(prescan(b"<meta charset=utf-16le>"), Some(Encoding::Utf8));
+    }
+
+    #[test]
+    fn prescan_charset_attribute_overrides_content() {
+        assert_eq!(
+            prescan(
+                b"<meta http-equiv=content-type content='charset=koi8-r' charset=euc-kr>"
+            ),
+            Some(Encoding::EucKr));
+        // a content attribute after it is ignored
+        assert_eq!(
+            prescan(
+                b"<meta charset=euc-kr http-equiv=content-type content='charset=koi8-r'>"
+            ),
+            Some(Encoding::EucKr));
+    }
+
+    #[test]
+    fn sniff_without_hints() {
+        assert_eq!(sniff(b"<p>\xC3\xA9", None), (Encoding::Utf8, Confidence::Tentative));
+        assert_eq!(sniff(b"<p>\xE9", None), (Encoding::Windows1252, Confidence::Tentative));
+        assert_eq!(sniff(b"<p>", None), (Encoding::Windows1252, Confidence::Tentative));
+    }
+
+    #[test]
+    fn sniff_allows_utf8_cut_off_at_sniff_len() {
+        let mut bytes = vec![b'a'; SNIFF_LEN - 1];
+        bytes.extend_from_slice("\u{E9}".as_bytes());
+        assert_eq!(sniff(&bytes, None), (Encoding::Utf8, Confidence::Tentative));
+    }
+
+    #[test]
+    fn gb18030() {
+        assert_decodes(Encoding::Gb18030, b"\xC4\xE3\xBA\xC3\x80", "\u{4F60}\u{597D}\u{20AC}");
+        assert_decodes(Encoding::Gb18030, b"\x81\x30\x81\x30\x95\x32\x82\x36", "\u{80}\u{20000}");
+        assert_decodes(Encoding::Gb18030, b"\x84\x31\xA4\x39\x84\x31\xA5\x30", "\u{FFFF}\u{FFFD}");
+        assert_decodes(Encoding::Gb18030, b"\x81\x30\x20", "\u{FFFD}0 ");
+        assert_decodes(Encoding::Gb18030, b"\x81\x30\x81", "\u{FFFD}");
+        assert_decodes(Encoding::Gbk, b"\xC4\xE3\x81\x30\x81\x30", "\u{4F60}\u{80}");
+    }
+
+    #[test]
+    fn big5() {
+        assert_decodes(Encoding::Big5, b"\xA4\xA4\xA4\xE5\xC8\x7A", "\u{4E2D}\u{6587}\u{200CC}");
+        assert_decodes(Encoding::Big5, b"\x88\x62\x88\x64", "\u{CA}\u{304}\u{CA}\u{30C}");
+        assert_decodes(Encoding::Big5, b"\x81\x41\xA4", "\u{FFFD}A\u{FFFD}");
+    }
+
+    #[test]
+    fn euc_kr() {
+        assert_decodes(Encoding::EucKr, b"\xC7\xD1\xB1\xDB", "\u{D55C}\u{AE00}");
+        assert_decodes(Encoding::EucKr, b"\x81\x20\xC7", "\u{FFFD} \u{FFFD}");
+    }
+
+    #[test]
+    fn euc_jp() {
+        assert_decodes(Encoding::EucJp, b"\xA4\xA2\x8E\xB1\x8F\xB0\xA1", "\u{3042}\u{FF71}\u{4E02}");
+        assert_decodes(Encoding::EucJp, b"\x8F\xA2\x20\x8F\xB0", "\u{FFFD} \u{FFFD}");
+    }
+
+    #[test]
+    fn shift_jis() {
+        assert_decodes(Encoding::ShiftJis, b"\x82\xA0\xB1", "\u{3042}\u{FF71}");
+        assert_decodes(Encoding::ShiftJis, b"\x82\x20", "\u{FFFD} ");
+    }
+
+    #[test]
+    fn single_byte() {
+        assert_decodes(Encoding::Windows1252, b"\x80\x81\xE9", "\u{20AC}\u{81}\u{E9}");
+        assert_decodes(Encoding::Koi8R, b"\xC1", "\u{430}");
+        assert_decodes(Encoding::XUserDefined, b"a\x80", "a\u{F780}");
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(Encoding::from_label(" GB2312 "), Some(Encoding::Gbk));
+        assert_eq!(Encoding::from_label("big5-hkscs"), Some(Encoding::Big5));
+        assert_eq!(Encoding::from_label("windows-949"), Some(Encoding::EucKr));
+        assert_eq!(Encoding::from_label("bogus"), None);
+    }
+}
@@ -0,0 +1,203 @@
+use super::Encoding;
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, 0x09 | 0x0A | 0x0C | 0x0D | 0x20)
+}
+
+fn starts_with_insensitive(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(bytes: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    bytes.get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|idx| idx + from)
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#prescan-a-byte-stream-to-determine-its-encoding
+pub fn prescan(bytes: &[u8]) -> Option<Encoding> {
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+        if rest.starts_with(b"<!--") {
+            // the dashes of the opening can close it too, as in <!-->
+            position = find(bytes, b"-->", position + 2)? + 3;
+            continue;
+        }
+        if starts_with_insensitive(rest, b"<meta") &&
+            rest.get(5).is_some_and(|&byte| is_whitespace(byte) || byte == b'/') {
+            position += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut position)? {
+                return Some(encoding);
+            }
+            continue;
+        }
+        if rest.len() > 2 && (
+            (rest[0] == b'<' && rest[1].is_ascii_alphabetic()) ||
+            (rest[0] == b'<' && rest[1] == b'/' && rest[2].is_ascii_alphabetic())
+        ) {
+            while position < bytes.len() &&
+                !is_whitespace(bytes[position]) &&
+                bytes[position] != b'>' {
+                position += 1;
+            }
+            while get_attribute(bytes, &mut position)?.is_some() {}
+            position += 1;
+            continue;
+        }
+        if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position = find(bytes, b">", position)? + 1;
+            continue;
+        }
+        position += 1;
+    }
+    None
+}
+
+// the outer None means the bytes ran out, the inner one that this meta
+// element didn't declare a usable encoding
+fn meta_encoding(bytes: &[u8], position: &mut usize) -> Option<Option<Encoding>> {
+    let mut attribute_list: Vec<Vec<u8>> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(bytes, position)? {
+        if attribute_list.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" if value == b"content-type" => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = extract_from_content(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            },
+            b"charset" => {
+                charset = Encoding::from_label(&String::from_utf8_lossy(&value));
+                need_pragma = Some(false);
+            },
+            _ => {},
+        }
+        attribute_list.push(name);
+    }
+    *position += 1;
+    match need_pragma {
+        None => return Some(None),
+        Some(true) if !got_pragma => return Some(None),
+        _ => {},
+    }
+    let encoding = match charset {
+        Some(Encoding::Utf16Be) |
+        Some(Encoding::Utf16Le) => Some(Encoding::Utf8),
+        Some(Encoding::XUserDefined) => Some(Encoding::Windows1252),
+        charset => charset,
+    };
+    Some(encoding)
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#concept-get-attributes-when-sniffing
+fn get_attribute(
+    bytes: &[u8],
+    position: &mut usize) -> Option<Option<(Vec<u8>, Vec<u8>)>> {
+    while is_whitespace(*bytes.get(*position)?) || bytes[*position] == b'/' {
+        *position += 1;
+    }
+    if bytes[*position] == b'>' {
+        return Some(None);
+    }
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    loop {
+        let byte = *bytes.get(*position)?;
+        match byte {
+            b'=' if !name.is_empty() => {
+                *position += 1;
+                return get_attribute_value(bytes, position, name);
+            },
+            _ if is_whitespace(byte) => break,
+            b'/' | b'>' => return Some(Some((name, value))),
+            _ => name.push(byte.to_ascii_lowercase()),
+        }
+        *position += 1;
+    }
+    while is_whitespace(*bytes.get(*position)?) {
+        *position += 1;
+    }
+    if bytes[*position] != b'=' {
+        return Some(Some((name, value)));
+    }
+    *position += 1;
+    get_attribute_value(bytes, position, name)
+}
+
+fn get_attribute_value(
+    bytes: &[u8],
+    position: &mut usize,
+    name: Vec<u8>) -> Option<Option<(Vec<u8>, Vec<u8>)>> {
+    let mut value = Vec::new();
+    while is_whitespace(*bytes.get(*position)?) {
+        *position += 1;
+    }
+    let byte = bytes[*position];
+    if byte == b'"' || byte == b'\'' {
+        loop {
+            *position += 1;
+            let next = *bytes.get(*position)?;
+            if next == byte {
+                *position += 1;
+                return Some(Some((name, value)));
+            }
+            value.push(next.to_ascii_lowercase());
+        }
+    }
+    if byte == b'>' {
+        return Some(Some((name, value)));
+    }
+    loop {
+        let next = *bytes.get(*position)?;
+        if is_whitespace(next) || next == b'>' {
+            return Some(Some((name, value)));
+        }
+        value.push(next.to_ascii_lowercase());
+        *position += 1;
+    }
+}
+
+//https://html.spec.whatwg.org/multipage/urls-and-fetching.html#algorithm-for-extracting-a-character-encoding-from-a-meta-element
+pub fn extract_from_content(content: &[u8]) -> Option<Encoding> {
+    let mut position = 0;
+    loop {
+        let found = content
+            .get(position..)?
+            .windows(7)
+            .position(|window| window.eq_ignore_ascii_case(b"charset"))?;
+        position += found + 7;
+        while content.get(position).is_some_and(|&byte| is_whitespace(byte)) {
+            position += 1;
+        }
+        if content.get(position) == Some(&b'=') {
+            break;
+        }
+    }
+    position += 1;
+    while content.get(position).is_some_and(|&byte| is_whitespace(byte)) {
+        position += 1;
+    }
+    let rest = content.get(position..)?;
+    let label = match rest.first()? {
+        &quote @ (b'"' | b'\'') => {
+            let end = rest[1..].iter().position(|&byte| byte == quote)?;
+            &rest[1..end + 1]
+        },
+        _ => {
+            let end = rest
+                .iter()
+                .position(|&byte| is_whitespace(byte) || byte == b';')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        },
+    };
+    Encoding::from_label(&String::from_utf8_lossy(label))
+}
@@ -0,0 +1,178 @@
+use crate::stream::Position;
+
+//https://html.spec.whatwg.org/multipage/parsing.html#parse-errors
+// the tokenizer errors carry the names the spec gives them, tree
+// construction only ever says "parse error" so those are named after
+// what the parser ran into
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterInInputStream,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NestedComment,
+    NoncharacterCharacterReference,
+    NoncharacterInInputStream,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+
+    // tree construction
+    MissingDoctype,
+    NonConformingDoctype,
+    UnexpectedDoctype,
+    UnexpectedCharacters,
+    UnexpectedStartTag(String),
+    UnexpectedEndTag(String),
+    UnexpectedEof,
+}
+
+impl ParseErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            Self::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            Self::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            Self::AbsenceOfDigitsInNumericCharacterReference =>
+                "absence-of-digits-in-numeric-character-reference",
+            Self::CdataInHtmlContent => "cdata-in-html-content",
+            Self::CharacterReferenceOutsideUnicodeRange =>
+                "character-reference-outside-unicode-range",
+            Self::ControlCharacterInInputStream => "control-character-in-input-stream",
+            Self::ControlCharacterReference => "control-character-reference",
+            Self::DuplicateAttribute => "duplicate-attribute",
+            Self::EndTagWithAttributes => "end-tag-with-attributes",
+            Self::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            Self::EofBeforeTagName => "eof-before-tag-name",
+            Self::EofInCdata => "eof-in-cdata",
+            Self::EofInComment => "eof-in-comment",
+            Self::EofInDoctype => "eof-in-doctype",
+            Self::EofInScriptHtmlCommentLikeText => "eof-in-script-html-comment-like-text",
+            Self::EofInTag => "eof-in-tag",
+            Self::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            Self::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            Self::InvalidCharacterSequenceAfterDoctypeName =>
+                "invalid-character-sequence-after-doctype-name",
+            Self::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            Self::MissingAttributeValue => "missing-attribute-value",
+            Self::MissingDoctypeName => "missing-doctype-name",
+            Self::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            Self::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            Self::MissingEndTagName => "missing-end-tag-name",
+            Self::MissingQuoteBeforeDoctypePublicIdentifier =>
+                "missing-quote-before-doctype-public-identifier",
+            Self::MissingQuoteBeforeDoctypeSystemIdentifier =>
+                "missing-quote-before-doctype-system-identifier",
+            Self::MissingSemicolonAfterCharacterReference =>
+                "missing-semicolon-after-character-reference",
+            Self::MissingWhitespaceAfterDoctypePublicKeyword =>
+                "missing-whitespace-after-doctype-public-keyword",
+            Self::MissingWhitespaceAfterDoctypeSystemKeyword =>
+                "missing-whitespace-after-doctype-system-keyword",
+            Self::MissingWhitespaceBeforeDoctypeName => "missing-whitespace-before-doctype-name",
+            Self::MissingWhitespaceBetweenAttributes => "missing-whitespace-between-attributes",
+            Self::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers =>
+                "missing-whitespace-between-doctype-public-and-system-identifiers",
+            Self::NestedComment => "nested-comment",
+            Self::NoncharacterCharacterReference => "noncharacter-character-reference",
+            Self::NoncharacterInInputStream => "noncharacter-in-input-stream",
+            Self::NonVoidHtmlElementStartTagWithTrailingSolidus =>
+                "non-void-html-element-start-tag-with-trailing-solidus",
+            Self::NullCharacterReference => "null-character-reference",
+            Self::SurrogateCharacterReference => "surrogate-character-reference",
+            Self::UnexpectedCharacterAfterDoctypeSystemIdentifier =>
+                "unexpected-character-after-doctype-system-identifier",
+            Self::UnexpectedCharacterInAttributeName => "unexpected-character-in-attribute-name",
+            Self::UnexpectedCharacterInUnquotedAttributeValue =>
+                "unexpected-character-in-unquoted-attribute-value",
+            Self::UnexpectedEqualsSignBeforeAttributeName =>
+                "unexpected-equals-sign-before-attribute-name",
+            Self::UnexpectedNullCharacter => "unexpected-null-character",
+            Self::UnexpectedQuestionMarkInsteadOfTagName =>
+                "unexpected-question-mark-instead-of-tag-name",
+            Self::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            Self::UnknownNamedCharacterReference => "unknown-named-character-reference",
+
+            Self::MissingDoctype => "missing-doctype",
+            Self::NonConformingDoctype => "non-conforming-doctype",
+            Self::UnexpectedDoctype => "unexpected-doctype",
+            Self::UnexpectedCharacters => "unexpected-characters",
+            Self::UnexpectedStartTag(_) => "unexpected-start-tag",
+            Self::UnexpectedEndTag(_) => "unexpected-end-tag",
+            Self::UnexpectedEof => "unexpected-eof",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub position: Position,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, position: Position) -> Self {
+        Self { kind, position }
+    }
+
+    pub fn present(&self) -> String {
+        let mut fmt_str = format!(
+            "{}:{} {}",
+            self.position.line,
+            self.position.column,
+            self.kind.code()
+        );
+        match &self.kind {
+            ParseErrorKind::UnexpectedStartTag(name) => {
+                fmt_str.push_str(" <");
+                fmt_str.push_str(name);
+                fmt_str.push('>');
+            },
+            ParseErrorKind::UnexpectedEndTag(name) => {
+                fmt_str.push_str(" </");
+                fmt_str.push_str(name);
+                fmt_str.push('>');
+            },
+            _ => {}
+        }
+        fmt_str
+    }
+}
@@ -1,3 +1,6 @@
+pub mod encoding;
+pub mod entities;
+pub mod errors;
 pub mod tokens;
 pub mod dom;
 pub mod parser;
@@ -10,31 +13,14 @@ pub enum HTMLError {
     ParserLostOriginalMode,
     InaccessibleDomTreeNode,
     OrphanObject,
-    ParseError,
 }
 
-pub fn make_dom(data: &Vec<u8>) -> Result<(), HTMLError> {
-    let mut tokenizer = tokenizer::Tokenizer::new(data);
-    let mut parser = parser::Parser::new();
-    loop {
-        let next_emit : Option<tokens::Token>  = tokenizer.get_next_token()?;
-        if let Some(token) = next_emit {
-            match token {
-                tokens::Token::EndOfFile => break,
-                _ => {
-                    println!("[TOKENIZER EMIT: {:?}]", token.present());
-                    match parser.parse_token(token) {
-                        Ok(wrapped_return) => {
-                            if let Some(state) = wrapped_return {
-                                println!("[PARSER EMIT: CHANGE Tokenizer TO {:?}]", state);
-                                tokenizer.state = state;
-                            }
-                        },
-                        Err(err) => { return Err(err); }
-                    }
-                }
-            }
-        }
-    }
-    Ok(())
+// the transport encoding is the charset the bytes were served with, if
+// known
+pub fn make_dom(
+    data: &[u8],
+    transport_encoding: Option<&str>) -> Result<(dom::DomTree, Vec<errors::ParseError>), HTMLError> {
+    let mut parser = parser::Parser::new(transport_encoding);
+    parser.feed(data)?;
+    parser.finish()
 }
@@ -3,47 +3,237 @@ use crate::{
     html::{
         tokenizer,
         HTMLError,
-        tokens::Token,
+        errors::{ParseError, ParseErrorKind},
+        encoding::{self, Confidence, Decoder, Encoding},
+        tokens::{Attribute, Token, Tag},
         dom,
-        dom::{DomObject, DomTree}
-    }
+        dom::{DomObject, DomTree, Element, Namespace}
+    },
+    stream::Span,
 };
 use std::mem;
 
 type TokenizerState = tokenizer::States;
 type ParserResult = Result<Option<TokenizerState>, HTMLError>;
 
-fn cmp_token_string(token_string: &[&u8], cmp: &str) -> bool {
-    if token_string.len() != cmp.len() {
-        false
-    } else {
-        for (i, val) in cmp.as_bytes().iter().enumerate() {
-            if val != token_string[i] {
-                return false
-            }
+fn char_is_whitespacish(char: char) -> bool {
+    match char {
+        '\t' |
+        '\n'/* LF */ |
+        '\u{000C}' /* FF */ |
+        ' ' => true,
+        _ => false,
+    }
+}
+
+// modes that treat whitespace specially only do so up to the first
+// non-whitespace character, so a run is split into its leading whitespace
+// and whatever follows
+fn split_leading_whitespace(data: &str) -> (&str, &str) {
+    let idx = data
+        .find(|char| !char_is_whitespacish(char))
+        .unwrap_or(data.len());
+    data.split_at(idx)
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+fn adjust_svg_tag_name(name: String) -> String {
+    let adjusted = match name.as_str() {
+        "altglyph" => "altGlyph",
+        "altglyphdef" => "altGlyphDef",
+        "altglyphitem" => "altGlyphItem",
+        "animatecolor" => "animateColor",
+        "animatemotion" => "animateMotion",
+        "animatetransform" => "animateTransform",
+        "clippath" => "clipPath",
+        "feblend" => "feBlend",
+        "fecolormatrix" => "feColorMatrix",
+        "fecomponenttransfer" => "feComponentTransfer",
+        "fecomposite" => "feComposite",
+        "feconvolvematrix" => "feConvolveMatrix",
+        "fediffuselighting" => "feDiffuseLighting",
+        "fedisplacementmap" => "feDisplacementMap",
+        "fedistantlight" => "feDistantLight",
+        "fedropshadow" => "feDropShadow",
+        "feflood" => "feFlood",
+        "fefunca" => "feFuncA",
+        "fefuncb" => "feFuncB",
+        "fefuncg" => "feFuncG",
+        "fefuncr" => "feFuncR",
+        "fegaussianblur" => "feGaussianBlur",
+        "feimage" => "feImage",
+        "femerge" => "feMerge",
+        "femergenode" => "feMergeNode",
+        "femorphology" => "feMorphology",
+        "feoffset" => "feOffset",
+        "fepointlight" => "fePointLight",
+        "fespecularlighting" => "feSpecularLighting",
+        "fespotlight" => "feSpotLight",
+        "fetile" => "feTile",
+        "feturbulence" => "feTurbulence",
+        "foreignobject" => "foreignObject",
+        "glyphref" => "glyphRef",
+        "lineargradient" => "linearGradient",
+        "radialgradient" => "radialGradient",
+        "textpath" => "textPath",
+        _ => return name,
+    };
+    String::from(adjusted)
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#adjust-mathml-attributes
+fn adjust_mathml_attributes(attributes: &mut [Attribute]) {
+    for attribute in attributes {
+        if attribute.name == "definitionurl" {
+            attribute.name = String::from("definitionURL");
         }
-        true
     }
 }
 
-fn dom_string_from_token_string(token_string: &[&u8]) -> String {
-    String::from_utf8(token_string
-        .iter()
-        .map(|&x| *x)
-        .collect()).unwrap()
+//https://html.spec.whatwg.org/multipage/parsing.html#adjust-svg-attributes
+fn adjust_svg_attributes(attributes: &mut [Attribute]) {
+    for attribute in attributes {
+        let adjusted = match attribute.name.as_str() {
+            "attributename" => "attributeName",
+            "attributetype" => "attributeType",
+            "basefrequency" => "baseFrequency",
+            "baseprofile" => "baseProfile",
+            "calcmode" => "calcMode",
+            "clippathunits" => "clipPathUnits",
+            "diffuseconstant" => "diffuseConstant",
+            "edgemode" => "edgeMode",
+            "filterunits" => "filterUnits",
+            "glyphref" => "glyphRef",
+            "gradienttransform" => "gradientTransform",
+            "gradientunits" => "gradientUnits",
+            "kernelmatrix" => "kernelMatrix",
+            "kernelunitlength" => "kernelUnitLength",
+            "keypoints" => "keyPoints",
+            "keysplines" => "keySplines",
+            "keytimes" => "keyTimes",
+            "lengthadjust" => "lengthAdjust",
+            "limitingconeangle" => "limitingConeAngle",
+            "markerheight" => "markerHeight",
+            "markerunits" => "markerUnits",
+            "markerwidth" => "markerWidth",
+            "maskcontentunits" => "maskContentUnits",
+            "maskunits" => "maskUnits",
+            "numoctaves" => "numOctaves",
+            "pathlength" => "pathLength",
+            "patterncontentunits" => "patternContentUnits",
+            "patterntransform" => "patternTransform",
+            "patternunits" => "patternUnits",
+            "pointsatx" => "pointsAtX",
+            "pointsaty" => "pointsAtY",
+            "pointsatz" => "pointsAtZ",
+            "preservealpha" => "preserveAlpha",
+            "preserveaspectratio" => "preserveAspectRatio",
+            "primitiveunits" => "primitiveUnits",
+            "refx" => "refX",
+            "refy" => "refY",
+            "repeatcount" => "repeatCount",
+            "repeatdur" => "repeatDur",
+            "requiredextensions" => "requiredExtensions",
+            "requiredfeatures" => "requiredFeatures",
+            "specularconstant" => "specularConstant",
+            "specularexponent" => "specularExponent",
+            "spreadmethod" => "spreadMethod",
+            "startoffset" => "startOffset",
+            "stddeviation" => "stdDeviation",
+            "stitchtiles" => "stitchTiles",
+            "surfacescale" => "surfaceScale",
+            "systemlanguage" => "systemLanguage",
+            "tablevalues" => "tableValues",
+            "targetx" => "targetX",
+            "targety" => "targetY",
+            "textlength" => "textLength",
+            "viewbox" => "viewBox",
+            "viewtarget" => "viewTarget",
+            "xchannelselector" => "xChannelSelector",
+            "ychannelselector" => "yChannelSelector",
+            "zoomandpan" => "zoomAndPan",
+            _ => continue,
+        };
+        attribute.name = String::from(adjusted);
+    }
 }
 
-fn byte_is_whitespacish(byte: &u8) -> bool {
-    match byte {
-        b'\t' |
-        b'\n'/* LF */ |
-        0x0C /* FF */ |
-        b' ' => true,
-        _ => false,
+//https://html.spec.whatwg.org/multipage/parsing.html#adjust-foreign-attributes
+// the prefix is dropped along with the colon, the namespace says it all
+fn adjust_foreign_attributes(attributes: &mut [Attribute]) {
+    for attribute in attributes {
+        let namespace = match attribute.name.as_str() {
+            "xlink:actuate" |
+            "xlink:arcrole" |
+            "xlink:href" |
+            "xlink:role" |
+            "xlink:show" |
+            "xlink:title" |
+            "xlink:type" => Namespace::XLink,
+            "xml:lang" |
+            "xml:space" => Namespace::Xml,
+            "xmlns" |
+            "xmlns:xlink" => Namespace::Xmlns,
+            _ => continue,
+        };
+        if let Some((_, local_name)) = attribute.name.split_once(':') {
+            attribute.name = String::from(local_name);
+        }
+        attribute.namespace = Some(namespace);
+    }
+}
+
+//https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+fn is_void_element(name: &str) -> bool {
+    matches!(name,
+        "area" | "base" | "basefont" | "bgsound" | "br" | "col" | "embed" |
+        "frame" | "hr" | "img" | "input" | "keygen" | "link" | "meta" |
+        "param" | "source" | "track" | "wbr")
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-scope
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#list-of-active-formatting-elements
+// elements keep the tag they were made for, so they can be made again
+#[derive(Debug, Clone)]
+enum ActiveFormatting {
+    Marker,
+    Element(ArenaRef, Tag),
+}
+
+// two formatting elements count as the same when their names and
+// attributes match, whatever order the attributes came in
+fn same_formatting(a: &Tag, b: &Tag) -> bool {
+    a.name == b.name &&
+        a.attributes.len() == b.attributes.len() &&
+        a.attributes.iter().all(|attribute| b.attributes.contains(attribute))
+}
+
+// a node goes in the parent, before the given child or last if there's
+// none
+struct InsertionLocation {
+    parent: ArenaRef,
+    before: Option<ArenaRef>,
+}
+
+impl InsertionLocation {
+    fn inside(parent: ArenaRef) -> Self {
+        Self {
+            parent,
+            before: None,
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Initial,
     BeforeHtml,
@@ -71,344 +261,3972 @@ pub enum Mode {
 }
 
 pub struct Parser {
-    // template_insertion_modes: Vec<Mode>,
+    template_insertion_modes: Vec<Mode>,
     insertion_mode: Mode,
     original_mode: Option<Mode>,
     open_elements: Vec<ArenaRef>,
     last_element: ArenaRef,
+    //https://html.spec.whatwg.org/multipage/parsing.html#frameset-ok-flag
+    frameset_ok: bool,
+    //https://html.spec.whatwg.org/multipage/parsing.html#form-element-pointer
+    form_element: Option<ArenaRef>,
+    // pre, listing and textarea drop a newline straight after the start tag
+    ignore_next_newline: bool,
+    active_formatting: Vec<ActiveFormatting>,
+    //https://html.spec.whatwg.org/multipage/parsing.html#foster-parent
+    // set while a token misplaced in a table is handled, nodes then go in
+    // front of the table rather than inside it
+    foster_parenting: bool,
+    pending_table_characters: String,
+    pending_table_span: Span,
     dom_tree: DomTree,
+    errors: Vec<ParseErrorKind>,
+    // source of the token being processed, given to every node it creates
+    token_span: Span,
+    // set when a meta element declares an encoding other than the tentative
+    // one being parsed with, parsing has to start over when it is
+    encoding_change: Option<Encoding>,
+    tokenizer: tokenizer::Tokenizer,
+    transport_encoding: Option<String>,
+    // every byte fed so far, kept so parsing can start over in another
+    // encoding
+    input: Vec<u8>,
+    // none until enough input has arrived to sniff the encoding
+    decoder: Option<Decoder>,
+    finished: bool,
+    // set once the end of the file has been processed
+    stopped: bool,
+    parse_errors: Vec<ParseError>,
+    //https://html.spec.whatwg.org/multipage/parsing.html#scripting-flag
+    // with scripting enabled the host runs scripts as their end tags are
+    // parsed, otherwise parsing never stops for them
+    scripting: bool,
+    //https://html.spec.whatwg.org/multipage/parsing.html#pending-parsing-blocking-script
+    pending_script: Option<ArenaRef>,
+    script_nesting_level: usize,
+    // the parser pause flag, nothing is tokenized from the input while a
+    // script is waiting to run or running
+    paused: bool,
+    // once a script has written markup the input no longer matches the
+    // bytes, so it can't be parsed over again in another encoding
+    markup_written: bool,
 }
 
 impl Parser {
-    pub fn new() -> Self {
+    // the transport encoding is the charset the bytes were served with,
+    // if known
+    pub fn new(transport_encoding: Option<&str>) -> Self {
         Self {
-            // template_insertion_modes: vec![Mode::Initial],
+            template_insertion_modes: Vec::new(),
             insertion_mode: Mode::Initial,
             original_mode: None,
-            open_elements: vec![0], 
+            open_elements: vec![0],
             last_element: 0,
+            frameset_ok: true,
+            form_element: None,
+            ignore_next_newline: false,
+            active_formatting: Vec::new(),
+            foster_parenting: false,
+            pending_table_characters: String::new(),
+            pending_table_span: Span::default(),
             dom_tree: DomTree::new(DomObject::Document),
+            errors: Vec::new(),
+            token_span: Span::default(),
+            encoding_change: None,
+            tokenizer: tokenizer::Tokenizer::new(),
+            transport_encoding: transport_encoding.map(String::from),
+            input: Vec::new(),
+            decoder: None,
+            finished: false,
+            stopped: false,
+            parse_errors: Vec::new(),
+            scripting: false,
+            pending_script: None,
+            script_nesting_level: 0,
+            paused: false,
+            markup_written: false,
+        }
+    }
+
+    pub fn set_scripting(&mut self, scripting: bool) {
+        self.scripting = scripting;
+    }
+
+    // hands over a script element whose end tag has been parsed. Parsing
+    // stays paused until the host has run it and calls resume, markup it
+    // writes in the meantime goes in at the insertion point.
+    pub fn take_pending_script(&mut self) -> Option<ArenaRef> {
+        // a script written by another one waits for that to finish
+        if self.script_nesting_level > 0 {
+            return None;
+        }
+        let script = self.pending_script.take()?;
+        self.tokenizer.set_insertion_point_here();
+        self.script_nesting_level += 1;
+        Some(script)
+    }
+
+    //https://html.spec.whatwg.org/multipage/dynamic-markup-insertion.html#document-write-steps
+    // the markup is tokenized right away, up to the insertion point, unless
+    // a script it contains has to run first. Without a script running there
+    // is no insertion point and the markup is dropped.
+    pub fn write(&mut self, markup: &str) -> Result<(), HTMLError> {
+        if self.tokenizer.insertion_point().is_none() {
+            return Ok(());
+        }
+        self.markup_written = true;
+        self.tokenizer.insert_input(markup);
+        while self.pending_script.is_none() && self.process_next_token()? {}
+        Ok(())
+    }
+
+    // the host is done running the script it took
+    pub fn resume(&mut self) -> Result<(), HTMLError> {
+        if self.script_nesting_level == 0 {
+            return Ok(());
+        }
+        self.script_nesting_level -= 1;
+        self.tokenizer.set_insertion_point(None);
+        // a script written by the one that just ran is next
+        self.paused = self.pending_script.is_some();
+        self.run()
+    }
+
+    // bytes can be split anywhere, even in the middle of a character, and
+    // the tree comes out the same as if they had all been fed at once
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), HTMLError> {
+        self.input.extend_from_slice(bytes);
+        match self.decoder {
+            Some(ref mut decoder) => {
+                let decoded = decoder.decode(bytes, false);
+                self.tokenizer.push_input(&decoded);
+            },
+            None => self.sniff_encoding(),
+        }
+        self.run()
+    }
+
+    // parse errors don't stop parsing, they are collected in the order they
+    // are encountered and handed back with the tree
+    pub fn finish(mut self) -> Result<(DomTree, Vec<ParseError>), HTMLError> {
+        self.end_input()?;
+        // the host won't run any more scripts, those still waiting are
+        // skipped
+        while self.paused {
+            self.take_pending_script();
+            self.resume()?;
+        }
+        Ok((self.dom_tree, self.parse_errors))
+    }
+
+    fn end_input(&mut self) -> Result<(), HTMLError> {
+        self.finished = true;
+        match self.decoder {
+            Some(ref mut decoder) => {
+                let decoded = decoder.decode(&[], true);
+                self.tokenizer.push_input(&decoded);
+            },
+            None => self.sniff_encoding(),
+        }
+        self.tokenizer.finish_input();
+        self.run()
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#encoding-sniffing-algorithm
+    // waits for the bytes sniffing looks at, unless a transport encoding
+    // makes it only a matter of checking for a BOM. A label that isn't an
+    // encoding is ignored, so the prescan still needs its bytes.
+    fn sniff_encoding(&mut self) {
+        let wanted = match self.transport_encoding.as_deref() {
+            Some(label) if Encoding::from_label(label).is_some() => 3,
+            _ => encoding::SNIFF_LEN,
+        };
+        if !self.finished && self.input.len() < wanted {
+            return;
+        }
+        let (encoding, confidence) = encoding::sniff(
+            &self.input,
+            self.transport_encoding.as_deref()
+        );
+        self.start_decoding(encoding, confidence);
+    }
+
+    fn start_decoding(&mut self, encoding: Encoding, confidence: Confidence) {
+        self.dom_tree.set_encoding(encoding, confidence);
+        let mut decoder = Decoder::new(encoding);
+        let decoded = decoder.decode(&self.input, self.finished);
+        self.tokenizer.push_input(&decoded);
+        self.decoder = Some(decoder);
+    }
+
+    fn run(&mut self) -> Result<(), HTMLError> {
+        while !self.paused && self.process_next_token()? {}
+        Ok(())
+    }
+
+    // hands the next token to tree construction, returns false when the
+    // tokenizer is out of input or the end of the file has been reached
+    fn process_next_token(&mut self) -> Result<bool, HTMLError> {
+        if self.stopped || self.decoder.is_none() || self.tokenizer.needs_input() {
+            return Ok(false);
+        }
+        self.tokenizer.foreign_content = self.adjusted_current_node_is_foreign();
+        let next_emit = self.tokenizer.get_next_token()?;
+        self.parse_errors.append(&mut self.tokenizer.take_errors());
+        let (token, span) = match next_emit {
+            Some(emit) => emit,
+            None => return Ok(true),
+        };
+        self.stopped = token == Token::EndOfFile;
+        if let Some(state) = self.parse_token(token, span)? {
+            self.tokenizer.state = state;
+        }
+        if let Some(declared) = self.encoding_change.take() {
+            self.restart(declared)?;
+            return Ok(false);
+        }
+        for kind in mem::take(&mut self.errors) {
+            self.parse_errors.push(ParseError::new(kind, span.start));
+        }
+        Ok(!self.stopped)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#changing-the-encoding-while-parsing
+    // everything is thrown away and the input so far parsed again. The
+    // declared encoding is certain, so this only ever happens once.
+    fn restart(&mut self, declared: Encoding) -> Result<(), HTMLError> {
+        let input = mem::take(&mut self.input);
+        let finished = self.finished;
+        let scripting = self.scripting;
+        *self = Parser::new(self.transport_encoding.as_deref());
+        self.input = input;
+        self.scripting = scripting;
+        self.start_decoding(declared, Confidence::Certain);
+        self.run()?;
+        if finished {
+            self.end_input()?;
+        }
+        Ok(())
+    }
+
+    fn parse_error(&mut self, kind: ParseErrorKind) {
+        self.errors.push(kind);
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#tree-construction-dispatcher
+    pub fn parse_token(&mut self, token: Token, span: Span) -> ParserResult {
+        self.token_span = span;
+        let token = match token {
+            Token::Characters(data) if mem::take(&mut self.ignore_next_newline) => {
+                match data.strip_prefix('\n') {
+                    Some("") => return Ok(None),
+                    Some(rest) => Token::Characters(rest.to_string()),
+                    None => Token::Characters(data),
+                }
+            },
+            token => {
+                self.ignore_next_newline = false;
+                token
+            },
+        };
+        if self.use_insertion_mode_for(&token) {
+            // only void elements and foreign elements acknowledge the
+            // self-closing flag
+            if let Token::StartTag(ref tag) = token {
+                if tag.self_closing &&
+                    !is_void_element(&tag.name) &&
+                    tag.name != "math" &&
+                    tag.name != "svg" {
+                    self.parse_error(
+                        ParseErrorKind::NonVoidHtmlElementStartTagWithTrailingSolidus
+                    );
+                }
+            }
+            self.insertion_mode_ruleset(token)
+        } else {
+            self.foreign_content_ruleset(token)
         }
     }
 
-    pub fn parse_token(&mut self, token: Token) -> ParserResult {
-        // if let Some(state) = self.template_insertion_modes.pop() {
-        println!("[PARSER STATE: {:?}]", self.insertion_mode);
+    fn insertion_mode_ruleset(&mut self, token: Token) -> ParserResult {
         match self.insertion_mode {
             Mode::Initial => self.initial_ruleset(token),
             Mode::BeforeHtml => self.before_html_ruleset(token),
             Mode::BeforeHead => self.before_head_ruleset(token),
             Mode::InHead => self.in_head_ruleset(token),
-            Mode::InHeadNoscript => todo!(),
+            Mode::InHeadNoscript => self.in_head_noscript_ruleset(token),
             Mode::AfterHead => self.after_head_ruleset(token),
             Mode::InBody => self.in_body_ruleset(token),
             Mode::Text => self.in_text_ruleset(token),
+            Mode::InTable => self.in_table_ruleset(token),
+            Mode::InTableText => self.in_table_text_ruleset(token),
+            Mode::InCaption => self.in_caption_ruleset(token),
+            Mode::InColumnGroup => self.in_column_group_ruleset(token),
+            Mode::InTableBody => self.in_table_body_ruleset(token),
+            Mode::InRow => self.in_row_ruleset(token),
+            Mode::InCell => self.in_cell_ruleset(token),
+            Mode::InSelect => self.in_select_ruleset(token),
+            Mode::InSelectInTable => self.in_select_in_table_ruleset(token),
+            Mode::InTemplate => self.in_template_ruleset(token),
             Mode::AfterBody => self.after_body_ruleset(token),
+            Mode::InFrameset => self.in_frameset_ruleset(token),
+            Mode::AfterFrameset => self.after_frameset_ruleset(token),
             Mode::AfterAfterBody => self.after_after_body_ruleset(token),
-            _ => todo!()
+            Mode::AfterAfterFrameset => self.after_after_frameset_ruleset(token),
         }
-        // } else {
-        //     Err(HTMLError::ParserWithoutInsertionMode)
-        // }
     }
 
+    //https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
     fn initial_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::Character(byte) => { todo!() },
-            Token::Comment(bytes) => { todo!() },
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (_, rest) = split_leading_whitespace(&data);
+                self.reprocess_remaining_characters(rest)
+            },
+            Token::Comment(data) => {
+                let document_ref = self.document_ref()?;
+                self.insert_comment_into(&data, document_ref)?;
+                Ok(None)
+            },
             Token::Doctype(doctype) => {
-                // make a new doc type node
-                if cmp_token_string(&doctype.name, "html") {
-                    self.dom_tree.insert(
-                        DomObject::DocumentType(
-                            dom::DocumentType::new(
-                                dom_string_from_token_string(&doctype.name),
-                                None,
-                                None 
-                            )
-                        ), 0
-                    )?;
-                    // self.template_insertion_modes.push(Mode::BeforeHtml);
-                    self.insertion_mode = Mode::BeforeHtml;
-                    Ok(None)
-                } else {
-                    // parser error
-                    Err(HTMLError::ParseError)
+                let name = doctype.name.clone();
+                let public_id = doctype.public_id
+                    .clone();
+                let system_id = doctype.system_id
+                    .clone();
+                if name != "html"
+                    || public_id.is_some()
+                    || system_id.as_deref().is_some_and(|id| id != "about:legacy-compat") {
+                    self.parse_error(ParseErrorKind::NonConformingDoctype);
                 }
+                self.dom_tree.set_quirks_mode(dom::quirks_mode_from_doctype(
+                    &name,
+                    public_id.as_deref(),
+                    system_id.as_deref(),
+                    doctype.force_quirks
+                ));
+                let document_ref = self.document_ref()?;
+                let doctype_ref = self.dom_tree.insert(
+                    DomObject::DocumentType(
+                        dom::DocumentType::new(name, public_id, system_id)
+                    ), document_ref, self.token_span
+                )?;
+                self.dom_tree.set_doctype(doctype_ref);
+                self.insertion_mode = Mode::BeforeHtml;
+                Ok(None)
+            },
+            _ => {
+                self.parse_error(ParseErrorKind::MissingDoctype);
+                self.dom_tree.set_quirks_mode(dom::QuirksMode::Quirks);
+                self.insertion_mode = Mode::BeforeHtml;
+                self.insertion_mode_ruleset(token)
             },
-            _ => { todo!() },
         }
     }
 
+    //https://html.spec.whatwg.org/multipage/parsing.html#the-before-html-insertion-mode
     fn before_html_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
             Token::Doctype(_) => {
-                Err(HTMLError::ParseError)
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
             },
-            Token::Comment(bytes) => {
-                todo!()
+            Token::Comment(data) => {
+                let document_ref = self.document_ref()?;
+                self.insert_comment_into(&data, document_ref)?;
+                Ok(None)
             },
-            Token::Character(byte) => {
-                match byte {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => Ok(None),
-                    _ => {
-                        todo!()
-                    }
-                }
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (_, rest) = split_leading_whitespace(&data);
+                self.reprocess_remaining_characters(rest)
             },
-            Token::StartTag(tag) => {
-                if cmp_token_string(&tag.name, "html") {
-                    let html_ref = self.dom_tree.insert(
-                        DomObject::Element(String::from("html")), 0
-                    )?;
-                    self.open_elements.push(html_ref);
-                    self.insertion_mode = Mode::BeforeHead;
-                    Ok(None)
-                } else {
-                    todo!()
-                }
+            Token::StartTag(tag) if tag.name == "html" => {
+                self.insert_html_root(tag.attributes, self.token_span)?;
+                Ok(None)
             },
-            Token::EndTag(tag) => {
-                todo!()
+            Token::EndTag(ref tag) if !matches!(tag.name.as_str(),
+                "head" | "body" | "html" | "br") => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
             },
             _ => {
-                todo!()
-            }
+                self.insert_html_root(Vec::new(), self.implied_span())?;
+                self.insertion_mode_ruleset(token)
+            },
         }
     }
 
+    // the html element goes straight into the Document
+    fn insert_html_root(
+        &mut self,
+        attributes: Vec<Attribute>,
+        span: Span) -> Result<(), HTMLError> {
+        let document_ref = self.document_ref()?;
+        let html_ref = self.dom_tree.insert(
+            DomObject::Element(
+                Element::new(String::from("html"), Namespace::Html, attributes)
+            ), document_ref, span
+        )?;
+        self.open_elements.push(html_ref);
+        self.insertion_mode = Mode::BeforeHead;
+        Ok(())
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#the-before-head-insertion-mode
     fn before_head_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::Character(byte) => {
-                match byte {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => Ok(None),
-                    _ => {
-                        todo!()
-                    }
-                }
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (_, rest) = split_leading_whitespace(&data);
+                self.reprocess_remaining_characters(rest)
             },
-            Token::Comment(bytes) => todo!(),
-            Token::Doctype(_) => todo!(),
-            Token::StartTag(tag) => {
-                if cmp_token_string(&tag.name, "html") {
-                    todo!()
-                }
-                if cmp_token_string(&tag.name, "head") {
-                    let head_ref = self.dom_tree.insert(
-                        DomObject::Head, 0
-                    )?;
-                    self.dom_tree.set_head(head_ref);
-                    self.open_elements.push(head_ref);
-                    self.insertion_mode = Mode::InHead;
-                    Ok(None)
-                } else { todo!() }
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) if tag.name == "head" => {
+                let head_ref = self.insert_html_element(tag)?;
+                self.dom_tree.set_head(head_ref);
+                self.insertion_mode = Mode::InHead;
+                Ok(None)
+            },
+            Token::EndTag(ref tag) if !matches!(tag.name.as_str(),
+                "head" | "body" | "html" | "br") => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            _ => {
+                let head_ref = self.insert_implied_html_element("head")?;
+                self.dom_tree.set_head(head_ref);
+                self.insertion_mode = Mode::InHead;
+                self.insertion_mode_ruleset(token)
             },
-            _ => todo!()
         }
     }
 
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inhead
     fn in_head_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::Character(byte) => {
-                match byte {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => Ok(None),
-                    _ => {
-                        todo!()
-                    }
-                }
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (whitespace, rest) = split_leading_whitespace(&data);
+                self.insert_or_merge_text_into_tree(whitespace.to_string())?;
+                self.reprocess_remaining_characters(rest)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
             },
-            Token::Comment(bytes) => todo!(),
-            Token::Doctype(_) => todo!(),
             Token::StartTag(ref tag) => {
-                match dom_string_from_token_string(&tag.name).as_str() {
+                match tag.name.clone().as_str() {
+                    "html" => self.in_body_ruleset(token),
+                    "base" |
+                    "basefont" |
+                    "bgsound" |
+                    "link" => {
+                        self.insert_html_element(tag)?;
+                        self.open_elements.pop();
+                        Ok(None)
+                    },
+                    "meta" => {
+                        self.insert_html_element(tag)?;
+                        self.open_elements.pop();
+                        if self.dom_tree.confidence() == Confidence::Tentative {
+                            // a charset that isn't a supported label is
+                            // passed over for the content type, if any
+                            let declared = tag.get_attribute("charset")
+                                .and_then(Encoding::from_label)
+                                .or_else(|| tag.get_attribute("http-equiv")
+                                    .filter(|value| value.eq_ignore_ascii_case("content-type"))
+                                    .and(tag.get_attribute("content"))
+                                    .and_then(|content| {
+                                        encoding::extract_from_content(content.as_bytes())
+                                    }));
+                            if let Some(declared) = declared {
+                                self.change_the_encoding(declared);
+                            }
+                        }
+                        Ok(None)
+                    },
                     "title" => {
-                        self.generic_rcdata_element_ruleset(&token)
+                        self.generic_rcdata_element_ruleset(tag)
+                    },
+                    "noscript" if self.scripting => {
+                        self.generic_raw_text_element_ruleset(tag)
+                    },
+                    "noframes" |
+                    "style" => {
+                        self.generic_raw_text_element_ruleset(tag)
                     },
-                    _ => todo!(),
+                    "noscript" => {
+                        self.insert_html_element(tag)?;
+                        self.insertion_mode = Mode::InHeadNoscript;
+                        Ok(None)
+                    },
+                    "script" => {
+                        self.insert_html_element(tag)?;
+                        self.original_mode = Some(self.insertion_mode);
+                        self.insertion_mode = Mode::Text;
+                        Ok(Some(TokenizerState::ScriptData))
+                    },
+                    "template" => {
+                        self.insert_html_element(tag)?;
+                        self.active_formatting.push(ActiveFormatting::Marker);
+                        self.frameset_ok = false;
+                        self.insertion_mode = Mode::InTemplate;
+                        self.template_insertion_modes.push(Mode::InTemplate);
+                        Ok(None)
+                    },
+                    "head" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                    _ => self.in_head_anything_else(token),
                 }
             },
-            Token::EndTag(_) => {
-                println!("{:?}", self.open_elements);
-                self.open_elements.pop();
-                self.insertion_mode = Mode::AfterHead;
-                Ok(None)
+            Token::EndTag(ref tag) => {
+                match tag.name.as_str() {
+                    "head" => {
+                        self.open_elements.pop();
+                        self.insertion_mode = Mode::AfterHead;
+                        Ok(None)
+                    },
+                    "body" |
+                    "html" |
+                    "br" => self.in_head_anything_else(token),
+                    "template" => {
+                        if !self.has_open_element("template") {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                            return Ok(None);
+                        }
+                        self.generate_all_implied_end_tags_thoroughly();
+                        if !self.current_node_is(&["template"]) {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        }
+                        self.pop_until("template");
+                        self.clear_active_formatting_to_last_marker();
+                        self.template_insertion_modes.pop();
+                        self.reset_insertion_mode();
+                        Ok(None)
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                }
             },
-            _ => { todo!() }
+            _ => self.in_head_anything_else(token),
         }
     }
-    
-    fn in_text_ruleset(&mut self, token: Token) -> ParserResult {
+
+    fn in_head_anything_else(&mut self, token: Token) -> ParserResult {
+        self.open_elements.pop();
+        self.insertion_mode = Mode::AfterHead;
+        self.insertion_mode_ruleset(token)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inheadnoscript
+    fn in_head_noscript_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::Character(byte) => {
-                self.insert_or_merge_text_into_tree(
-                    String::from_utf8(vec![*byte]).unwrap()
-                    )?;
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
                 Ok(None)
             },
-            Token::EndTag(tag) => {
-                self.open_elements.pop();
-                if let Some(original_mode) = self.original_mode {
-                    self.insertion_mode = original_mode; 
-                    Ok(None)
-                } else {
-                    Err(HTMLError::ParserLostOriginalMode)
-                }
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (whitespace, rest) = split_leading_whitespace(&data);
+                self.in_head_ruleset(Token::Characters(whitespace.to_string()))?;
+                self.reprocess_remaining_characters(rest)
             },
-            _ => todo!()
-        }
-    }
-
-    fn after_head_ruleset(&mut self, token: Token) -> ParserResult {
-        match token {
-            Token::Character(byte) => {
-                match byte {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => {
-                        self.insert_or_merge_text_into_tree(
-                            String::from_utf8(vec![*byte]).unwrap()
-                            )?;
+            Token::Comment(_) => self.in_head_ruleset(token),
+            Token::StartTag(ref tag) => {
+                match tag.name.as_str() {
+                    "html" => self.in_body_ruleset(token),
+                    "basefont" |
+                    "bgsound" |
+                    "link" |
+                    "meta" |
+                    "noframes" |
+                    "style" => self.in_head_ruleset(token),
+                    "head" |
+                    "noscript" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
                         Ok(None)
                     },
-                    _ => todo!(),
+                    _ => self.in_head_noscript_anything_else(token),
                 }
             },
-            Token::StartTag(tag) => {
-                let tag_name = dom_string_from_token_string(&tag.name);
-                match tag_name.as_str() {
-                    "body" => {
-                        let body_ref = self.dom_tree.insert(
-                            DomObject::Element(
-                                tag_name,
-                            ), *self.open_elements.last().unwrap() // TODO: cleanup
-                        )?;
-                        self.open_elements.push(body_ref);
-                        // TODO: frameset-ok = 'not ok'
-                        self.insertion_mode = Mode::InBody;
+            Token::EndTag(ref tag) => {
+                match tag.name.as_str() {
+                    "noscript" => {
+                        self.open_elements.pop();
+                        self.insertion_mode = Mode::InHead;
+                        Ok(None)
+                    },
+                    "br" => self.in_head_noscript_anything_else(token),
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
                         Ok(None)
                     },
-                    _ => todo!(),
                 }
             },
-            _ => todo!()
+            _ => self.in_head_noscript_anything_else(token),
         }
     }
 
-    fn in_body_ruleset(&mut self, token: Token) -> ParserResult {
+    fn in_head_noscript_anything_else(&mut self, token: Token) -> ParserResult {
+        self.report_unexpected(&token);
+        self.open_elements.pop();
+        self.insertion_mode = Mode::InHead;
+        self.insertion_mode_ruleset(token)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incdata
+    fn in_text_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::Character(byte) => {
-                self.insert_or_merge_text_into_tree(
-                    String::from_utf8(vec![*byte]).unwrap()
-                    )?;
+            Token::Characters(data) => {
+                self.insert_or_merge_text_into_tree(data)?;
                 Ok(None)
             },
+            Token::EndOfFile => {
+                self.parse_error(ParseErrorKind::UnexpectedEof);
+                self.open_elements.pop();
+                self.insertion_mode = self.original_mode
+                    .ok_or(HTMLError::ParserLostOriginalMode)?;
+                self.insertion_mode_ruleset(token)
+            },
             Token::EndTag(tag) => {
-                let tag_name = dom_string_from_token_string(&tag.name);
-                match tag_name.as_str() {
-                    "body" => {
-                        // TODO: check if body is in scope
-                        // TODO: check rest of elements in stack
-                        self.insertion_mode = Mode::AfterBody;
-                        Ok(None)
-                    },
-                    _ => todo!()
+                let node = self.open_elements.pop();
+                let original_mode = self.original_mode
+                    .ok_or(HTMLError::ParserLostOriginalMode)?;
+                self.insertion_mode = original_mode;
+                //https://html.spec.whatwg.org/multipage/parsing.html#scriptEndTag
+                // the host runs the script, parsing picks up again once it
+                // is done
+                if tag.name == "script" && self.scripting {
+                    self.pending_script = node;
+                    self.paused = true;
                 }
+                Ok(None)
             },
-            _ => todo!()
+            // the tokenizer is in a text state, nothing else can come out
+            _ => Ok(None),
         }
     }
 
-    fn after_body_ruleset(&mut self, token: Token) -> ParserResult {
+    //https://html.spec.whatwg.org/multipage/parsing.html#the-after-head-insertion-mode
+    fn after_head_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::Character(byte) => {
-                if byte_is_whitespacish(byte) {
-                    self.in_body_ruleset(token)
-                } else {
-                    todo!()
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (whitespace, rest) = split_leading_whitespace(&data);
+                self.insert_or_merge_text_into_tree(whitespace.to_string())?;
+                self.reprocess_remaining_characters(rest)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) => {
+                match tag.name.clone().as_str() {
+                    "html" => self.in_body_ruleset(token),
+                    "body" => {
+                        self.insert_html_element(tag)?;
+                        self.frameset_ok = false;
+                        self.insertion_mode = Mode::InBody;
+                        Ok(None)
+                    },
+                    "frameset" => {
+                        self.insert_html_element(tag)?;
+                        self.insertion_mode = Mode::InFrameset;
+                        Ok(None)
+                    },
+                    "base" |
+                    "basefont" |
+                    "bgsound" |
+                    "link" |
+                    "meta" |
+                    "noframes" |
+                    "script" |
+                    "style" |
+                    "template" |
+                    "title" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        let head_ref = self.dom_tree.get_head()
+                            .ok_or(HTMLError::InaccessibleDomTreeNode)?;
+                        self.open_elements.push(head_ref);
+                        let result = self.in_head_ruleset(token);
+                        self.open_elements.retain(|&node| node != head_ref);
+                        result
+                    },
+                    "head" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                    _ => self.after_head_anything_else(token),
                 }
             },
-            Token::EndTag(tag) => {
-                let tag_name = dom_string_from_token_string(&tag.name);
-                match tag_name.as_str() {
-                    "html" => {
-                        self.insertion_mode = Mode::AfterAfterBody;
+            Token::EndTag(ref tag) => {
+                match tag.name.as_str() {
+                    "template" => self.in_head_ruleset(token),
+                    "body" |
+                    "html" |
+                    "br" => self.after_head_anything_else(token),
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
                         Ok(None)
                     },
-                    _ => todo!()
                 }
             },
-            _ => todo!()
+            _ => self.after_head_anything_else(token),
         }
     }
 
-    fn after_after_body_ruleset(&mut self, token: Token) -> ParserResult {
-        match token {
-            Token::Character(byte) => {
-                if byte_is_whitespacish(byte) {
-                    self.in_body_ruleset(token)
-                } else {
-                    todo!()
-                }
-            },
-            _ => todo!()
-        }
+    fn after_head_anything_else(&mut self, token: Token) -> ParserResult {
+        self.insert_implied_html_element("body")?;
+        self.insertion_mode = Mode::InBody;
+        self.insertion_mode_ruleset(token)
     }
 
-    fn generic_rcdata_element_ruleset(&mut self, token: &Token) -> ParserResult {
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody
+    fn in_body_ruleset(&mut self, token: Token) -> ParserResult {
         match token {
-            Token::StartTag(tag) => {
-                self.dom_tree.insert(
-                    DomObject::Element(
-                        dom_string_from_token_string(&tag.name),
-                    ), *self.open_elements.last().unwrap() // TODO: cleanup
-                );
+            Token::Characters(data) => {
+                let data = self.remove_null_characters(data);
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                self.reconstruct_active_formatting_elements()?;
+                if !data.chars().all(char_is_whitespacish) {
+                    self.frameset_ok = false;
+                }
+                self.insert_or_merge_text_into_tree(data)?;
+                Ok(None)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::EndOfFile => {
+                if !self.template_insertion_modes.is_empty() {
+                    return self.in_template_ruleset(Token::EndOfFile);
+                }
+                if self.has_unclosed_elements() {
+                    self.parse_error(ParseErrorKind::UnexpectedEof);
+                }
+                Ok(None)
             },
-            _ => todo!(),
-        }
-        self.original_mode = Some(self.insertion_mode);
-        self.insertion_mode = Mode::Text;
-        Ok(Some(TokenizerState::RCData))
-    }
-    
-    fn generic_raw_text_element_ruleset() -> Result<(), HTMLError> {
-        todo!()
-    }
-
-    fn insert_into_tree(&mut self, obj: DomObject) -> Result<(), HTMLError> {
-        if let Some(parent_ref) = self.open_elements.last() {
-            let new_ref = self.dom_tree.insert(
-                obj, *parent_ref
-            )?;
-            self.last_element = new_ref;
-            Ok(())
-        } else {
-            Err(HTMLError::OrphanObject)
         }
     }
 
-    fn insert_or_merge_text_into_tree(&mut self, data: String) -> Result<(), HTMLError> {
-        if let Some(node) = self.dom_tree.arena.get_mut(self.last_element) {
-            match node.dom_obj {
-                DomObject::Text(ref mut string) => {
-                    string.push_str(&data);
-                    Ok(())
-                },
-                _ => {
-                    self.insert_into_tree(DomObject::Text(data))
+    fn in_body_start_tag(&mut self, tag: Tag) -> ParserResult {
+        match tag.name.as_str() {
+            "html" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                if !self.has_open_element("template") {
+                    if let Some(&html_ref) = self.open_elements.get(1) {
+                        self.add_missing_attributes(html_ref, &tag);
+                    }
                 }
-            }
-        } else {
-            Err(HTMLError::OrphanObject)
-        }
+                Ok(None)
+            },
+            "base" |
+            "basefont" |
+            "bgsound" |
+            "link" |
+            "meta" |
+            "noframes" |
+            "script" |
+            "style" |
+            "template" |
+            "title" => self.in_head_ruleset(Token::StartTag(tag)),
+            "body" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                let body_ref = self.open_elements
+                    .get(2)
+                    .copied()
+                    .filter(|&node| self.is_html_element(node, "body"));
+                if let Some(body_ref) = body_ref {
+                    if !self.has_open_element("template") {
+                        self.frameset_ok = false;
+                        self.add_missing_attributes(body_ref, &tag);
+                    }
+                }
+                Ok(None)
+            },
+            "frameset" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                let body_ref = self.open_elements
+                    .get(2)
+                    .copied()
+                    .filter(|&node| self.is_html_element(node, "body"));
+                if let Some(body_ref) = body_ref {
+                    if self.frameset_ok {
+                        self.dom_tree.detach(body_ref)?;
+                        self.open_elements.truncate(2);
+                        self.insert_html_element(&tag)?;
+                        self.insertion_mode = Mode::InFrameset;
+                    }
+                }
+                Ok(None)
+            },
+            "address" |
+            "article" |
+            "aside" |
+            "blockquote" |
+            "center" |
+            "details" |
+            "dialog" |
+            "dir" |
+            "div" |
+            "dl" |
+            "fieldset" |
+            "figcaption" |
+            "figure" |
+            "footer" |
+            "header" |
+            "hgroup" |
+            "main" |
+            "menu" |
+            "nav" |
+            "ol" |
+            "p" |
+            "search" |
+            "section" |
+            "summary" |
+            "ul" => {
+                self.close_p_element();
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_element();
+                if self.current_node_is(&["h1", "h2", "h3", "h4", "h5", "h6"]) {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    self.open_elements.pop();
+                }
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+            "pre" |
+            "listing" => {
+                self.close_p_element();
+                self.insert_html_element(&tag)?;
+                self.ignore_next_newline = true;
+                self.frameset_ok = false;
+                Ok(None)
+            },
+            "form" => {
+                let in_template = self.has_open_element("template");
+                if self.form_element.is_some() && !in_template {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.close_p_element();
+                let form_ref = self.insert_html_element(&tag)?;
+                if !in_template {
+                    self.form_element = Some(form_ref);
+                }
+                Ok(None)
+            },
+            "li" |
+            "dd" |
+            "dt" => {
+                self.frameset_ok = false;
+                let closes: &[&str] = match tag.name.as_str() {
+                    "li" => &["li"],
+                    _ => &["dd", "dt"],
+                };
+                for idx in (1..self.open_elements.len()).rev() {
+                    let node = self.open_elements[idx];
+                    if let Some(name) = closes
+                        .iter()
+                        .find(|&&name| self.is_html_element(node, name)) {
+                        let name = name.to_string();
+                        self.generate_implied_end_tags(Some(&name));
+                        if !self.current_node_is(&[name.as_str()]) {
+                            self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        }
+                        self.pop_until(&name);
+                        break;
+                    }
+                    if self.is_special(node) &&
+                        !self.is_one_of_html_elements(node, &["address", "div", "p"]) {
+                        break;
+                    }
+                }
+                self.close_p_element();
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+            "plaintext" => {
+                self.close_p_element();
+                self.insert_html_element(&tag)?;
+                Ok(Some(TokenizerState::PlainText))
+            },
+            "button" => {
+                if self.has_element_in_scope(&["button"], Scope::Default) {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    self.generate_implied_end_tags(None);
+                    self.pop_until("button");
+                }
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                self.frameset_ok = false;
+                Ok(None)
+            },
+            "a" => {
+                if let Some(node) = self.active_formatting_element("a") {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    self.adoption_agency("a")?;
+                    self.remove_active_formatting_element(node);
+                    self.open_elements.retain(|&open| open != node);
+                }
+                self.reconstruct_active_formatting_elements()?;
+                let node = self.insert_html_element(&tag)?;
+                self.push_active_formatting_element(node, tag);
+                Ok(None)
+            },
+            "b" |
+            "big" |
+            "code" |
+            "em" |
+            "font" |
+            "i" |
+            "s" |
+            "small" |
+            "strike" |
+            "strong" |
+            "tt" |
+            "u" => {
+                self.reconstruct_active_formatting_elements()?;
+                let node = self.insert_html_element(&tag)?;
+                self.push_active_formatting_element(node, tag);
+                Ok(None)
+            },
+            "nobr" => {
+                self.reconstruct_active_formatting_elements()?;
+                if self.has_element_in_scope(&["nobr"], Scope::Default) {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    self.adoption_agency("nobr")?;
+                    self.reconstruct_active_formatting_elements()?;
+                }
+                let node = self.insert_html_element(&tag)?;
+                self.push_active_formatting_element(node, tag);
+                Ok(None)
+            },
+            "applet" |
+            "marquee" |
+            "object" => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                self.active_formatting.push(ActiveFormatting::Marker);
+                self.frameset_ok = false;
+                Ok(None)
+            },
+            "table" => {
+                if self.dom_tree.quirks_mode() != dom::QuirksMode::Quirks {
+                    self.close_p_element();
+                }
+                self.insert_html_element(&tag)?;
+                self.frameset_ok = false;
+                self.insertion_mode = Mode::InTable;
+                Ok(None)
+            },
+            "area" |
+            "br" |
+            "embed" |
+            "img" |
+            "keygen" |
+            "wbr" => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                self.open_elements.pop();
+                self.frameset_ok = false;
+                Ok(None)
+            },
+            "input" => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                self.open_elements.pop();
+                if !tag.get_attribute("type")
+                    .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden")) {
+                    self.frameset_ok = false;
+                }
+                Ok(None)
+            },
+            "param" |
+            "source" |
+            "track" => {
+                self.insert_html_element(&tag)?;
+                self.open_elements.pop();
+                Ok(None)
+            },
+            "hr" => {
+                self.close_p_element();
+                self.insert_html_element(&tag)?;
+                self.open_elements.pop();
+                self.frameset_ok = false;
+                Ok(None)
+            },
+            "image" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                let tag = Tag {
+                    name: String::from("img"),
+                    ..tag
+                };
+                self.insertion_mode_ruleset(Token::StartTag(tag))
+            },
+            "textarea" => {
+                self.insert_html_element(&tag)?;
+                self.ignore_next_newline = true;
+                self.original_mode = Some(self.insertion_mode);
+                self.frameset_ok = false;
+                self.insertion_mode = Mode::Text;
+                Ok(Some(TokenizerState::RCData))
+            },
+            "xmp" => {
+                self.close_p_element();
+                self.reconstruct_active_formatting_elements()?;
+                self.frameset_ok = false;
+                self.generic_raw_text_element_ruleset(&tag)
+            },
+            "iframe" => {
+                self.frameset_ok = false;
+                self.generic_raw_text_element_ruleset(&tag)
+            },
+            "noembed" => self.generic_raw_text_element_ruleset(&tag),
+            "noscript" if self.scripting => {
+                self.generic_raw_text_element_ruleset(&tag)
+            },
+            "select" => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                self.frameset_ok = false;
+                self.insertion_mode = match self.insertion_mode {
+                    Mode::InTable |
+                    Mode::InCaption |
+                    Mode::InTableBody |
+                    Mode::InRow |
+                    Mode::InCell => Mode::InSelectInTable,
+                    _ => Mode::InSelect,
+                };
+                Ok(None)
+            },
+            "optgroup" |
+            "option" => {
+                if self.current_node_is(&["option"]) {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+            "rb" |
+            "rtc" => {
+                if self.has_element_in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    if !self.current_node_is(&["ruby"]) {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    }
+                }
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+            "rp" |
+            "rt" => {
+                if self.has_element_in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                    if !self.current_node_is(&["ruby", "rtc"]) {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    }
+                }
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+            "math" => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_foreign_element(&tag, Namespace::MathMl)?;
+                Ok(None)
+            },
+            "svg" => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_foreign_element(&tag, Namespace::Svg)?;
+                Ok(None)
+            },
+            "caption" |
+            "col" |
+            "colgroup" |
+            "frame" |
+            "head" |
+            "tbody" |
+            "td" |
+            "tfoot" |
+            "th" |
+            "thead" |
+            "tr" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                Ok(None)
+            },
+            _ => {
+                self.reconstruct_active_formatting_elements()?;
+                self.insert_html_element(&tag)?;
+                Ok(None)
+            },
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) -> ParserResult {
+        match tag.name.as_str() {
+            "template" => self.in_head_ruleset(Token::EndTag(tag)),
+            "body" |
+            "html" => {
+                if !self.has_element_in_scope(&["body"], Scope::Default) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                if self.has_unclosed_elements() {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                }
+                self.insertion_mode = Mode::AfterBody;
+                if tag.name == "html" {
+                    return self.insertion_mode_ruleset(Token::EndTag(tag));
+                }
+                Ok(None)
+            },
+            "address" |
+            "article" |
+            "aside" |
+            "blockquote" |
+            "button" |
+            "center" |
+            "details" |
+            "dialog" |
+            "dir" |
+            "div" |
+            "dl" |
+            "fieldset" |
+            "figcaption" |
+            "figure" |
+            "footer" |
+            "header" |
+            "hgroup" |
+            "listing" |
+            "main" |
+            "menu" |
+            "nav" |
+            "ol" |
+            "pre" |
+            "search" |
+            "section" |
+            "summary" |
+            "ul" |
+            "applet" |
+            "marquee" |
+            "object" => {
+                if !self.has_element_in_scope(&[tag.name.as_str()], Scope::Default) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_node_is(&[tag.name.as_str()]) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                }
+                self.pop_until(&tag.name);
+                if matches!(tag.name.as_str(), "applet" | "marquee" | "object") {
+                    self.clear_active_formatting_to_last_marker();
+                }
+                Ok(None)
+            },
+            "form" => {
+                if !self.has_open_element("template") {
+                    let form_ref = self.form_element.take();
+                    let form_ref = match form_ref {
+                        Some(form_ref) if self.has_node_in_scope(form_ref, Scope::Default) => {
+                            form_ref
+                        },
+                        _ => {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                            return Ok(None);
+                        },
+                    };
+                    self.generate_implied_end_tags(None);
+                    if self.current_node() != form_ref {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    }
+                    self.open_elements.retain(|&node| node != form_ref);
+                } else {
+                    if !self.has_element_in_scope(&["form"], Scope::Default) {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        return Ok(None);
+                    }
+                    self.generate_implied_end_tags(None);
+                    if !self.current_node_is(&["form"]) {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    }
+                    self.pop_until("form");
+                }
+                Ok(None)
+            },
+            "p" => {
+                if !self.has_element_in_scope(&["p"], Scope::Button) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    self.insert_implied_html_element("p")?;
+                }
+                self.close_p_element();
+                Ok(None)
+            },
+            "li" |
+            "dd" |
+            "dt" => {
+                let scope = if tag.name == "li" {
+                    Scope::ListItem
+                } else {
+                    Scope::Default
+                };
+                if !self.has_element_in_scope(&[tag.name.as_str()], scope) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.generate_implied_end_tags(Some(&tag.name));
+                if !self.current_node_is(&[tag.name.as_str()]) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                }
+                self.pop_until(&tag.name);
+                Ok(None)
+            },
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let headings = ["h1", "h2", "h3", "h4", "h5", "h6"];
+                if !self.has_element_in_scope(&headings, Scope::Default) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_node_is(&[tag.name.as_str()]) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                }
+                self.pop_until_one_of(&headings);
+                Ok(None)
+            },
+            "br" => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                self.in_body_start_tag(Tag {
+                    name: String::from("br"),
+                    ..Tag::default()
+                })
+            },
+            "a" |
+            "b" |
+            "big" |
+            "code" |
+            "em" |
+            "font" |
+            "i" |
+            "nobr" |
+            "s" |
+            "small" |
+            "strike" |
+            "strong" |
+            "tt" |
+            "u" => {
+                if self.adoption_agency(&tag.name)? {
+                    Ok(None)
+                } else {
+                    self.in_body_any_other_end_tag(&tag)
+                }
+            },
+            _ => self.in_body_any_other_end_tag(&tag),
+        }
+    }
+
+    fn in_body_any_other_end_tag(&mut self, tag: &Tag) -> ParserResult {
+        for idx in (1..self.open_elements.len()).rev() {
+            let node = self.open_elements[idx];
+            if self.is_html_element(node, &tag.name) {
+                self.generate_implied_end_tags(Some(&tag.name));
+                if node != self.current_node() {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                }
+                self.open_elements.truncate(idx);
+                return Ok(None);
+            }
+            if self.is_special(node) {
+                break;
+            }
+        }
+        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+        Ok(None)
+    }
+
+    fn active_formatting_index(&self, node_ref: ArenaRef) -> Option<usize> {
+        self.active_formatting
+            .iter()
+            .position(|entry| {
+                matches!(entry, ActiveFormatting::Element(node, _) if *node == node_ref)
+            })
+    }
+
+    // the last element with the name that comes after the last marker
+    fn active_formatting_element(&self, name: &str) -> Option<ArenaRef> {
+        self.active_formatting
+            .iter()
+            .rev()
+            .take_while(|entry| !matches!(entry, ActiveFormatting::Marker))
+            .find_map(|entry| match entry {
+                ActiveFormatting::Element(node, tag) if tag.name == name => Some(*node),
+                _ => None,
+            })
+    }
+
+    fn remove_active_formatting_element(&mut self, node_ref: ArenaRef) {
+        if let Some(idx) = self.active_formatting_index(node_ref) {
+            self.active_formatting.remove(idx);
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#push-onto-the-list-of-active-formatting-elements
+    // the Noah's Ark clause keeps at most three identical elements after the
+    // last marker, the earliest one goes to make room
+    fn push_active_formatting_element(&mut self, node_ref: ArenaRef, tag: Tag) {
+        let start = self.active_formatting
+            .iter()
+            .rposition(|entry| matches!(entry, ActiveFormatting::Marker))
+            .map_or(0, |idx| idx + 1);
+        let identical: Vec<usize> = (start..self.active_formatting.len())
+            .filter(|&idx| matches!(&self.active_formatting[idx],
+                ActiveFormatting::Element(_, other) if same_formatting(other, &tag)))
+            .collect();
+        if identical.len() >= 3 {
+            self.active_formatting.remove(identical[0]);
+        }
+        self.active_formatting.push(ActiveFormatting::Element(node_ref, tag));
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#clear-the-list-of-active-formatting-elements-up-to-the-last-marker
+    fn clear_active_formatting_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if matches!(entry, ActiveFormatting::Marker) {
+                break;
+            }
+        }
+    }
+
+    fn is_marker_or_open(&self, idx: usize) -> bool {
+        match &self.active_formatting[idx] {
+            ActiveFormatting::Marker => true,
+            ActiveFormatting::Element(node, _) => self.open_elements.contains(node),
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements
+    // formatting elements that were closed by something else get opened
+    // again, starting after the last one that is still open
+    fn reconstruct_active_formatting_elements(&mut self) -> Result<(), HTMLError> {
+        let len = self.active_formatting.len();
+        if len == 0 || self.is_marker_or_open(len - 1) {
+            return Ok(());
+        }
+        let mut start = len - 1;
+        while start > 0 && !self.is_marker_or_open(start - 1) {
+            start -= 1;
+        }
+        for idx in start..len {
+            if let ActiveFormatting::Element(_, tag) = self.active_formatting[idx].clone() {
+                let node = self.insert_html_element(&tag)?;
+                self.active_formatting[idx] = ActiveFormatting::Element(node, tag);
+            }
+        }
+        Ok(())
+    }
+
+    // a new element for the same tag as an existing one, it isn't put in the
+    // tree yet
+    fn clone_element(&mut self, node_ref: ArenaRef, tag: &Tag) -> ArenaRef {
+        let span = self.dom_tree.arena
+            .get(node_ref)
+            .map(|node| node.span)
+            .unwrap_or_default();
+        self.dom_tree.create(DomObject::Element(Element::new(
+            tag.name.clone(),
+            Namespace::Html,
+            tag.attributes.clone()
+        )), span)
+    }
+
+    fn formatting_tag_of(&self, idx: usize) -> Result<Tag, HTMLError> {
+        match &self.active_formatting[idx] {
+            ActiveFormatting::Element(_, tag) => Ok(tag.clone()),
+            ActiveFormatting::Marker => Err(HTMLError::InaccessibleDomTreeNode),
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+    // returns false when the end tag has to be handled like any other end
+    // tag instead
+    fn adoption_agency(&mut self, subject: &str) -> Result<bool, HTMLError> {
+        let current = self.current_node();
+        if self.is_html_element(current, subject) &&
+            self.active_formatting_index(current).is_none() {
+            self.open_elements.pop();
+            return Ok(true);
+        }
+        for _ in 0..8 {
+            let formatting = match self.active_formatting_element(subject) {
+                Some(node) => node,
+                None => return Ok(false),
+            };
+            let formatting_idx = match self.open_elements
+                .iter()
+                .position(|&node| node == formatting) {
+                Some(idx) => idx,
+                None => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(subject.to_string()));
+                    self.remove_active_formatting_element(formatting);
+                    return Ok(true);
+                },
+            };
+            if !self.has_node_in_scope(formatting, Scope::Default) {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(subject.to_string()));
+                return Ok(true);
+            }
+            if formatting != self.current_node() {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(subject.to_string()));
+            }
+            // the topmost special element below the formatting element
+            let furthest_block = (formatting_idx + 1..self.open_elements.len())
+                .map(|idx| self.open_elements[idx])
+                .find(|&node| self.is_special(node));
+            let furthest_block = match furthest_block {
+                Some(node) => node,
+                None => {
+                    self.open_elements.truncate(formatting_idx);
+                    self.remove_active_formatting_element(formatting);
+                    return Ok(true);
+                },
+            };
+            let common_ancestor = self.open_elements[formatting_idx - 1];
+            // where the formatting element's replacement goes in the list,
+            // counted as if the formatting element were still in it
+            let mut bookmark = self.active_formatting_index(formatting)
+                .ok_or(HTMLError::InaccessibleDomTreeNode)?;
+            let mut node_idx = self.open_elements
+                .iter()
+                .position(|&node| node == furthest_block)
+                .ok_or(HTMLError::InaccessibleDomTreeNode)?;
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_idx -= 1;
+                let node = self.open_elements[node_idx];
+                if node == formatting {
+                    break;
+                }
+                let mut entry_idx = self.active_formatting_index(node);
+                if inner_loop_counter > 3 {
+                    if let Some(idx) = entry_idx.take() {
+                        self.active_formatting.remove(idx);
+                        if idx < bookmark {
+                            bookmark -= 1;
+                        }
+                    }
+                }
+                let entry_idx = match entry_idx {
+                    Some(idx) => idx,
+                    None => {
+                        self.open_elements.remove(node_idx);
+                        continue;
+                    },
+                };
+                let tag = self.formatting_tag_of(entry_idx)?;
+                let new_node = self.clone_element(node, &tag);
+                self.active_formatting[entry_idx] = ActiveFormatting::Element(new_node, tag);
+                self.open_elements[node_idx] = new_node;
+                if last_node == furthest_block {
+                    bookmark = entry_idx + 1;
+                }
+                self.dom_tree.append(last_node, new_node)?;
+                last_node = new_node;
+            }
+            let location = self.appropriate_place_for_inserting(Some(common_ancestor));
+            self.dom_tree.insert_before(last_node, location.parent, location.before)?;
+            let formatting_entry = self.active_formatting_index(formatting)
+                .ok_or(HTMLError::InaccessibleDomTreeNode)?;
+            let tag = self.formatting_tag_of(formatting_entry)?;
+            let new_element = self.clone_element(formatting, &tag);
+            self.dom_tree.move_children(furthest_block, new_element)?;
+            self.dom_tree.append(new_element, furthest_block)?;
+            self.active_formatting.remove(formatting_entry);
+            if formatting_entry < bookmark {
+                bookmark -= 1;
+            }
+            self.active_formatting.insert(bookmark, ActiveFormatting::Element(new_element, tag));
+            self.open_elements.retain(|&node| node != formatting);
+            let furthest_block_idx = self.open_elements
+                .iter()
+                .position(|&node| node == furthest_block)
+                .ok_or(HTMLError::InaccessibleDomTreeNode)?;
+            self.open_elements.insert(furthest_block_idx + 1, new_element);
+        }
+        Ok(true)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intable
+    fn in_table_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(_) if self.current_node_is(&[
+                "table", "tbody", "template", "tfoot", "thead", "tr"
+            ]) => {
+                self.pending_table_characters.clear();
+                self.original_mode = Some(self.insertion_mode);
+                self.insertion_mode = Mode::InTableText;
+                self.insertion_mode_ruleset(token)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) => {
+                match tag.name.as_str() {
+                    "caption" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.active_formatting.push(ActiveFormatting::Marker);
+                        self.insert_html_element(tag)?;
+                        self.insertion_mode = Mode::InCaption;
+                        Ok(None)
+                    },
+                    "colgroup" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_html_element(tag)?;
+                        self.insertion_mode = Mode::InColumnGroup;
+                        Ok(None)
+                    },
+                    "col" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_implied_html_element("colgroup")?;
+                        self.insertion_mode = Mode::InColumnGroup;
+                        self.insertion_mode_ruleset(token)
+                    },
+                    "tbody" |
+                    "tfoot" |
+                    "thead" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_html_element(tag)?;
+                        self.insertion_mode = Mode::InTableBody;
+                        Ok(None)
+                    },
+                    "td" |
+                    "th" |
+                    "tr" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_implied_html_element("tbody")?;
+                        self.insertion_mode = Mode::InTableBody;
+                        self.insertion_mode_ruleset(token)
+                    },
+                    "table" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        if !self.has_element_in_scope(&["table"], Scope::Table) {
+                            return Ok(None);
+                        }
+                        self.pop_until("table");
+                        self.reset_insertion_mode();
+                        self.insertion_mode_ruleset(token)
+                    },
+                    "style" |
+                    "script" |
+                    "template" => self.in_head_ruleset(token),
+                    "input" if tag.get_attribute("type")
+                        .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden")) => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        self.insert_html_element(tag)?;
+                        self.open_elements.pop();
+                        Ok(None)
+                    },
+                    "form" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        if self.has_open_element("template") || self.form_element.is_some() {
+                            return Ok(None);
+                        }
+                        let form_ref = self.insert_html_element(tag)?;
+                        self.form_element = Some(form_ref);
+                        self.open_elements.pop();
+                        Ok(None)
+                    },
+                    _ => self.in_table_anything_else(token),
+                }
+            },
+            Token::EndTag(ref tag) => {
+                match tag.name.as_str() {
+                    "table" => {
+                        if !self.has_element_in_scope(&["table"], Scope::Table) {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                            return Ok(None);
+                        }
+                        self.pop_until("table");
+                        self.reset_insertion_mode();
+                        Ok(None)
+                    },
+                    "body" |
+                    "caption" |
+                    "col" |
+                    "colgroup" |
+                    "html" |
+                    "tbody" |
+                    "td" |
+                    "tfoot" |
+                    "th" |
+                    "thead" |
+                    "tr" => {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                    "template" => self.in_head_ruleset(token),
+                    _ => self.in_table_anything_else(token),
+                }
+            },
+            Token::EndOfFile => self.in_body_ruleset(token),
+            _ => self.in_table_anything_else(token),
+        }
+    }
+
+    fn in_table_anything_else(&mut self, token: Token) -> ParserResult {
+        self.report_unexpected(&token);
+        self.foster_parenting = true;
+        let result = self.in_body_ruleset(token);
+        self.foster_parenting = false;
+        result
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intabletext
+    // characters are held back until something else comes along, if any of
+    // them isn't whitespace the whole run gets foster parented
+    fn in_table_text_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) => {
+                let data = self.remove_null_characters(data);
+                if self.pending_table_characters.is_empty() {
+                    self.pending_table_span = self.token_span;
+                }
+                self.pending_table_characters.push_str(&data);
+                self.pending_table_span.end = self.token_span.end;
+                Ok(None)
+            },
+            _ => {
+                let pending = mem::take(&mut self.pending_table_characters);
+                // the text comes from where the characters were, not from
+                // the token that ended the run
+                let token_span = mem::replace(&mut self.token_span, self.pending_table_span);
+                let result = if pending.chars().all(char_is_whitespacish) {
+                    if pending.is_empty() {
+                        Ok(())
+                    } else {
+                        self.insert_or_merge_text_into_tree(pending)
+                    }
+                } else {
+                    self.in_table_anything_else(Token::Characters(pending)).map(|_| ())
+                };
+                self.token_span = token_span;
+                result?;
+                self.insertion_mode = self.original_mode
+                    .ok_or(HTMLError::ParserLostOriginalMode)?;
+                self.insertion_mode_ruleset(token)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incaption
+    fn in_caption_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::EndTag(ref tag) if tag.name == "caption" => {
+                self.close_caption(&token);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" |
+                "thead" | "tr") => {
+                if self.close_caption(&token) {
+                    self.insertion_mode_ruleset(token)
+                } else {
+                    Ok(None)
+                }
+            },
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_caption(&token) {
+                    self.insertion_mode_ruleset(token)
+                } else {
+                    Ok(None)
+                }
+            },
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(),
+                "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" |
+                "th" | "thead" | "tr") => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            _ => self.in_body_ruleset(token),
+        }
+    }
+
+    // returns false if there was no caption to close
+    fn close_caption(&mut self, token: &Token) -> bool {
+        if !self.has_element_in_scope(&["caption"], Scope::Table) {
+            self.report_unexpected(token);
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        if !self.current_node_is(&["caption"]) {
+            self.report_unexpected(token);
+        }
+        self.pop_until("caption");
+        self.clear_active_formatting_to_last_marker();
+        self.insertion_mode = Mode::InTable;
+        true
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incolgroup
+    fn in_column_group_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (whitespace, rest) = split_leading_whitespace(&data);
+                self.insert_or_merge_text_into_tree(whitespace.to_string())?;
+                self.reprocess_remaining_characters(rest)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) if tag.name == "col" => {
+                self.insert_html_element(tag)?;
+                self.open_elements.pop();
+                Ok(None)
+            },
+            Token::EndTag(ref tag) if tag.name == "colgroup" => {
+                if !self.current_node_is(&["colgroup"]) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.open_elements.pop();
+                self.insertion_mode = Mode::InTable;
+                Ok(None)
+            },
+            Token::EndTag(ref tag) if tag.name == "col" => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            Token::StartTag(ref tag) |
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head_ruleset(token),
+            Token::EndOfFile => self.in_body_ruleset(token),
+            _ => {
+                if !self.current_node_is(&["colgroup"]) {
+                    self.report_unexpected(&token);
+                    return Ok(None);
+                }
+                self.open_elements.pop();
+                self.insertion_mode = Mode::InTable;
+                self.insertion_mode_ruleset(token)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intbody
+    fn in_table_body_ruleset(&mut self, token: Token) -> ParserResult {
+        const TABLE_BODY_CONTEXT: &[&str] = &["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            Token::StartTag(ref tag) if tag.name == "tr" => {
+                self.clear_stack_back_to(TABLE_BODY_CONTEXT);
+                self.insert_html_element(tag)?;
+                self.insertion_mode = Mode::InRow;
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if tag.name == "th" || tag.name == "td" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                self.clear_stack_back_to(TABLE_BODY_CONTEXT);
+                self.insert_implied_html_element("tr")?;
+                self.insertion_mode = Mode::InRow;
+                self.insertion_mode_ruleset(token)
+            },
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.has_element_in_scope(&[tag.name.as_str()], Scope::Table) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.clear_stack_back_to(TABLE_BODY_CONTEXT);
+                self.open_elements.pop();
+                self.insertion_mode = Mode::InTable;
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") => {
+                self.close_table_body(token)
+            },
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_table_body(token),
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(),
+                "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr") => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            _ => self.in_table_ruleset(token),
+        }
+    }
+
+    fn close_table_body(&mut self, token: Token) -> ParserResult {
+        if !self.has_element_in_scope(&["tbody", "thead", "tfoot"], Scope::Table) {
+            self.report_unexpected(&token);
+            return Ok(None);
+        }
+        self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+        self.open_elements.pop();
+        self.insertion_mode = Mode::InTable;
+        self.insertion_mode_ruleset(token)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intr
+    fn in_row_ruleset(&mut self, token: Token) -> ParserResult {
+        const TABLE_ROW_CONTEXT: &[&str] = &["tr", "template", "html"];
+        match token {
+            Token::StartTag(ref tag) if tag.name == "th" || tag.name == "td" => {
+                self.clear_stack_back_to(TABLE_ROW_CONTEXT);
+                self.insert_html_element(tag)?;
+                self.insertion_mode = Mode::InCell;
+                self.active_formatting.push(ActiveFormatting::Marker);
+                Ok(None)
+            },
+            Token::EndTag(ref tag) if tag.name == "tr" => {
+                self.close_row(&token);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if self.close_row(&token) {
+                    self.insertion_mode_ruleset(token)
+                } else {
+                    Ok(None)
+                }
+            },
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_row(&token) {
+                    self.insertion_mode_ruleset(token)
+                } else {
+                    Ok(None)
+                }
+            },
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.has_element_in_scope(&[tag.name.as_str()], Scope::Table) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                if self.close_row(&token) {
+                    self.insertion_mode_ruleset(token)
+                } else {
+                    Ok(None)
+                }
+            },
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(),
+                "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th") => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            _ => self.in_table_ruleset(token),
+        }
+    }
+
+    // returns false if there was no row to close
+    fn close_row(&mut self, token: &Token) -> bool {
+        if !self.has_element_in_scope(&["tr"], Scope::Table) {
+            self.report_unexpected(token);
+            return false;
+        }
+        self.clear_stack_back_to(&["tr", "template", "html"]);
+        self.open_elements.pop();
+        self.insertion_mode = Mode::InTableBody;
+        true
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intd
+    fn in_cell_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::EndTag(ref tag) if tag.name == "td" || tag.name == "th" => {
+                if !self.has_element_in_scope(&[tag.name.as_str()], Scope::Table) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_node_is(&[tag.name.as_str()]) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                }
+                self.pop_until(&tag.name);
+                self.clear_active_formatting_to_last_marker();
+                self.insertion_mode = Mode::InRow;
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" |
+                "thead" | "tr") => {
+                if !self.has_element_in_scope(&["td", "th"], Scope::Table) {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.close_cell(&token);
+                self.insertion_mode_ruleset(token)
+            },
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(),
+                "body" | "caption" | "col" | "colgroup" | "html") => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(),
+                "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if !self.has_element_in_scope(&[tag.name.as_str()], Scope::Table) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.close_cell(&token);
+                self.insertion_mode_ruleset(token)
+            },
+            _ => self.in_body_ruleset(token),
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#close-the-cell
+    fn close_cell(&mut self, token: &Token) {
+        self.generate_implied_end_tags(None);
+        if !self.current_node_is(&["td", "th"]) {
+            self.report_unexpected(token);
+        }
+        self.pop_until_one_of(&["td", "th"]);
+        self.clear_active_formatting_to_last_marker();
+        self.insertion_mode = Mode::InRow;
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inselect
+    fn in_select_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) => {
+                let data = self.remove_null_characters(data);
+                if !data.is_empty() {
+                    self.insert_or_merge_text_into_tree(data)?;
+                }
+                Ok(None)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) => {
+                match tag.name.as_str() {
+                    "html" => self.in_body_ruleset(token),
+                    "option" => {
+                        if self.current_node_is(&["option"]) {
+                            self.open_elements.pop();
+                        }
+                        self.insert_html_element(tag)?;
+                        Ok(None)
+                    },
+                    "optgroup" |
+                    "hr" => {
+                        if self.current_node_is(&["option"]) {
+                            self.open_elements.pop();
+                        }
+                        if self.current_node_is(&["optgroup"]) {
+                            self.open_elements.pop();
+                        }
+                        self.insert_html_element(tag)?;
+                        if tag.name == "hr" {
+                            self.open_elements.pop();
+                        }
+                        Ok(None)
+                    },
+                    "select" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        if self.has_element_in_scope(&["select"], Scope::Select) {
+                            self.pop_until("select");
+                            self.reset_insertion_mode();
+                        }
+                        Ok(None)
+                    },
+                    "input" |
+                    "keygen" |
+                    "textarea" => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        if !self.has_element_in_scope(&["select"], Scope::Select) {
+                            return Ok(None);
+                        }
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                        self.insertion_mode_ruleset(token)
+                    },
+                    "script" |
+                    "template" => self.in_head_ruleset(token),
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                }
+            },
+            Token::EndTag(ref tag) => {
+                match tag.name.as_str() {
+                    "optgroup" => {
+                        let previous = self.open_elements
+                            .len()
+                            .checked_sub(2)
+                            .map(|idx| self.open_elements[idx]);
+                        if self.current_node_is(&["option"]) &&
+                            previous.is_some_and(|node| self.is_html_element(node, "optgroup")) {
+                            self.open_elements.pop();
+                        }
+                        if self.current_node_is(&["optgroup"]) {
+                            self.open_elements.pop();
+                        } else {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        }
+                        Ok(None)
+                    },
+                    "option" => {
+                        if self.current_node_is(&["option"]) {
+                            self.open_elements.pop();
+                        } else {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        }
+                        Ok(None)
+                    },
+                    "select" => {
+                        if !self.has_element_in_scope(&["select"], Scope::Select) {
+                            self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                            return Ok(None);
+                        }
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                        Ok(None)
+                    },
+                    "template" => self.in_head_ruleset(token),
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                }
+            },
+            Token::EndOfFile => self.in_body_ruleset(token),
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inselectintable
+    fn in_select_in_table_ruleset(&mut self, token: Token) -> ParserResult {
+        const TABLE_ELEMENTS: &[&str] = &[
+            "caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"
+        ];
+        match token {
+            Token::StartTag(ref tag) if TABLE_ELEMENTS.contains(&tag.name.as_str()) => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                self.pop_until("select");
+                self.reset_insertion_mode();
+                self.insertion_mode_ruleset(token)
+            },
+            Token::EndTag(ref tag) if TABLE_ELEMENTS.contains(&tag.name.as_str()) => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                if !self.has_element_in_scope(&[tag.name.as_str()], Scope::Table) {
+                    return Ok(None);
+                }
+                self.pop_until("select");
+                self.reset_insertion_mode();
+                self.insertion_mode_ruleset(token)
+            },
+            _ => self.in_select_ruleset(token),
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intemplate
+    fn in_template_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(_) |
+            Token::Comment(_) |
+            Token::Doctype(_) => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) => {
+                let mode = match tag.name.as_str() {
+                    "base" |
+                    "basefont" |
+                    "bgsound" |
+                    "link" |
+                    "meta" |
+                    "noframes" |
+                    "script" |
+                    "style" |
+                    "template" |
+                    "title" => return self.in_head_ruleset(token),
+                    "caption" |
+                    "colgroup" |
+                    "tbody" |
+                    "tfoot" |
+                    "thead" => Mode::InTable,
+                    "col" => Mode::InColumnGroup,
+                    "tr" => Mode::InTableBody,
+                    "td" |
+                    "th" => Mode::InRow,
+                    _ => Mode::InBody,
+                };
+                self.template_insertion_modes.pop();
+                self.template_insertion_modes.push(mode);
+                self.insertion_mode = mode;
+                self.insertion_mode_ruleset(token)
+            },
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head_ruleset(token),
+            Token::EndTag(ref tag) => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            Token::EndOfFile => {
+                if !self.has_open_element("template") {
+                    return Ok(None);
+                }
+                self.parse_error(ParseErrorKind::UnexpectedEof);
+                self.pop_until("template");
+                self.clear_active_formatting_to_last_marker();
+                self.template_insertion_modes.pop();
+                self.reset_insertion_mode();
+                self.insertion_mode_ruleset(token)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-afterbody
+    fn after_body_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (whitespace, rest) = split_leading_whitespace(&data);
+                self.in_body_ruleset(Token::Characters(whitespace.to_string()))?;
+                self.reprocess_remaining_characters(rest)
+            },
+            Token::Comment(data) => {
+                // the first element in the stack of open elements is the html
+                // element, which sits just above the Document
+                let html_ref = *self.open_elements
+                    .get(1)
+                    .ok_or(HTMLError::OrphanObject)?;
+                self.insert_comment_into(&data, html_ref)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body_ruleset(token),
+            Token::EndTag(ref tag) if tag.name == "html" => {
+                self.insertion_mode = Mode::AfterAfterBody;
+                Ok(None)
+            },
+            Token::EndOfFile => Ok(None),
+            _ => {
+                self.report_unexpected(&token);
+                self.insertion_mode = Mode::InBody;
+                self.insertion_mode_ruleset(token)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inframeset
+    fn in_frameset_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) => {
+                let whitespace = self.remove_non_whitespace(data);
+                if !whitespace.is_empty() {
+                    self.insert_or_merge_text_into_tree(whitespace)?;
+                }
+                Ok(None)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) => {
+                match tag.name.as_str() {
+                    "html" => self.in_body_ruleset(token),
+                    "frameset" => {
+                        self.insert_html_element(tag)?;
+                        Ok(None)
+                    },
+                    "frame" => {
+                        self.insert_html_element(tag)?;
+                        self.open_elements.pop();
+                        Ok(None)
+                    },
+                    "noframes" => self.in_head_ruleset(token),
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                        Ok(None)
+                    },
+                }
+            },
+            Token::EndTag(ref tag) if tag.name == "frameset" => {
+                if self.open_elements.len() <= 2 {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                    return Ok(None);
+                }
+                self.open_elements.pop();
+                if !self.current_node_is(&["frameset"]) {
+                    self.insertion_mode = Mode::AfterFrameset;
+                }
+                Ok(None)
+            },
+            Token::EndTag(ref tag) => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag(tag.name.clone()));
+                Ok(None)
+            },
+            Token::EndOfFile => {
+                if self.open_elements.len() > 2 {
+                    self.parse_error(ParseErrorKind::UnexpectedEof);
+                }
+                Ok(None)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-afterframeset
+    fn after_frameset_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) => {
+                let whitespace = self.remove_non_whitespace(data);
+                if !whitespace.is_empty() {
+                    self.insert_or_merge_text_into_tree(whitespace)?;
+                }
+                Ok(None)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head_ruleset(token),
+            Token::EndTag(ref tag) if tag.name == "html" => {
+                self.insertion_mode = Mode::AfterAfterFrameset;
+                Ok(None)
+            },
+            Token::EndOfFile => Ok(None),
+            _ => {
+                self.report_unexpected(&token);
+                Ok(None)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#the-after-after-body-insertion-mode
+    fn after_after_body_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) if data.starts_with(char_is_whitespacish) => {
+                let (whitespace, rest) = split_leading_whitespace(&data);
+                self.in_body_ruleset(Token::Characters(whitespace.to_string()))?;
+                self.reprocess_remaining_characters(rest)
+            },
+            Token::Comment(data) => {
+                let document_ref = self.document_ref()?;
+                self.insert_comment_into(&data, document_ref)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body_ruleset(token),
+            Token::EndOfFile => Ok(None),
+            _ => {
+                self.report_unexpected(&token);
+                self.insertion_mode = Mode::InBody;
+                self.insertion_mode_ruleset(token)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#the-after-after-frameset-insertion-mode
+    fn after_after_frameset_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) => {
+                let whitespace = self.remove_non_whitespace(data);
+                if !whitespace.is_empty() {
+                    self.in_body_ruleset(Token::Characters(whitespace))?;
+                }
+                Ok(None)
+            },
+            Token::Comment(data) => {
+                let document_ref = self.document_ref()?;
+                self.insert_comment_into(&data, document_ref)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body_ruleset(token),
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head_ruleset(token),
+            Token::EndOfFile => Ok(None),
+            _ => {
+                self.report_unexpected(&token);
+                Ok(None)
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#changing-the-encoding-while-parsing
+    fn change_the_encoding(&mut self, declared: Encoding) {
+        let current = self.dom_tree.encoding();
+        if matches!(current, Encoding::Utf16Be | Encoding::Utf16Le) {
+            self.dom_tree.set_encoding(current, Confidence::Certain);
+            return;
+        }
+        let declared = match declared {
+            Encoding::Utf16Be | Encoding::Utf16Le => Encoding::Utf8,
+            Encoding::XUserDefined => Encoding::Windows1252,
+            declared => declared,
+        };
+        if declared == current || self.markup_written {
+            self.dom_tree.set_encoding(current, Confidence::Certain);
+            return;
+        }
+        self.encoding_change = Some(declared);
+    }
+
+    // once the leading whitespace of a run has been dealt with, the rest of
+    // it is reprocessed in the same insertion mode
+    fn reprocess_remaining_characters(&mut self, rest: &str) -> ParserResult {
+        if rest.is_empty() {
+            Ok(None)
+        } else {
+            self.insertion_mode_ruleset(Token::Characters(rest.to_string()))
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#generic-rcdata-element-parsing-algorithm
+    fn generic_rcdata_element_ruleset(&mut self, tag: &Tag) -> ParserResult {
+        self.generic_text_element_ruleset(tag, TokenizerState::RCData)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#generic-raw-text-element-parsing-algorithm
+    fn generic_raw_text_element_ruleset(&mut self, tag: &Tag) -> ParserResult {
+        self.generic_text_element_ruleset(tag, TokenizerState::RawText)
+    }
+
+    fn generic_text_element_ruleset(
+        &mut self,
+        tag: &Tag,
+        state: TokenizerState) -> ParserResult {
+        self.insert_html_element(tag)?;
+        self.original_mode = Some(self.insertion_mode);
+        self.insertion_mode = Mode::Text;
+        Ok(Some(state))
+    }
+
+    // NULs are dropped with a parse error each
+    fn remove_null_characters(&mut self, data: String) -> String {
+        if !data.contains('\0') {
+            return data;
+        }
+        for _ in data.matches('\0') {
+            self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+        }
+        data.replace('\0', "")
+    }
+
+    // the frameset modes only keep whitespace, every other character is
+    // a parse error and gets dropped
+    fn remove_non_whitespace(&mut self, data: String) -> String {
+        for _ in data.chars().filter(|&char| !char_is_whitespacish(char)) {
+            self.parse_error(ParseErrorKind::UnexpectedCharacters);
+        }
+        data.chars().filter(|&char| char_is_whitespacish(char)).collect()
+    }
+
+    fn report_unexpected(&mut self, token: &Token) {
+        let kind = match token {
+            Token::Doctype(_) => ParseErrorKind::UnexpectedDoctype,
+            Token::StartTag(tag) => ParseErrorKind::UnexpectedStartTag(tag.name.clone()),
+            Token::EndTag(tag) => ParseErrorKind::UnexpectedEndTag(tag.name.clone()),
+            Token::Characters(_) => ParseErrorKind::UnexpectedCharacters,
+            Token::EndOfFile => ParseErrorKind::UnexpectedEof,
+            Token::Comment(_) => return,
+        };
+        self.parse_error(kind);
+    }
+
+    fn html_name_of(&self, node_ref: ArenaRef) -> Option<&str> {
+        self.element_of(node_ref)
+            .filter(|element| element.namespace() == Namespace::Html)
+            .map(|element| element.name())
+    }
+
+    fn is_html_element(&self, node_ref: ArenaRef, name: &str) -> bool {
+        self.html_name_of(node_ref) == Some(name)
+    }
+
+    fn is_one_of_html_elements(&self, node_ref: ArenaRef, names: &[&str]) -> bool {
+        self.html_name_of(node_ref)
+            .is_some_and(|name| names.contains(&name))
+    }
+
+    fn current_node(&self) -> ArenaRef {
+        self.open_elements.last().copied().unwrap_or(0)
+    }
+
+    fn current_node_is(&self, names: &[&str]) -> bool {
+        self.is_one_of_html_elements(self.current_node(), names)
+    }
+
+    fn has_open_element(&self, name: &str) -> bool {
+        self.open_elements
+            .iter()
+            .any(|&node| self.is_html_element(node, name))
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-the-specific-scope
+    fn in_scope(&self, scope: Scope, is_target: impl Fn(ArenaRef) -> bool) -> bool {
+        for &node in self.open_elements[1..].iter().rev() {
+            if is_target(node) {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn has_element_in_scope(&self, names: &[&str], scope: Scope) -> bool {
+        self.in_scope(scope, |node| self.is_one_of_html_elements(node, names))
+    }
+
+    fn has_node_in_scope(&self, node_ref: ArenaRef, scope: Scope) -> bool {
+        self.in_scope(scope, |node| node == node_ref)
+    }
+
+    fn is_scope_boundary(&self, node_ref: ArenaRef, scope: Scope) -> bool {
+        let name = self.html_name_of(node_ref);
+        match scope {
+            Scope::Table => matches!(name, Some("html" | "table" | "template")),
+            // everything but optgroup and option
+            Scope::Select => !matches!(name, Some("optgroup" | "option")),
+            _ => {
+                let extra: &[&str] = match scope {
+                    Scope::ListItem => &["ol", "ul"],
+                    Scope::Button => &["button"],
+                    _ => &[],
+                };
+                match name {
+                    Some(name) => {
+                        matches!(name,
+                            "applet" | "caption" | "html" | "table" | "td" | "th" |
+                            "marquee" | "object" | "template") ||
+                            extra.contains(&name)
+                    },
+                    None => self.element_of(node_ref)
+                        .is_some_and(Self::is_special_foreign_element),
+                }
+            },
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#generate-implied-end-tags
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        const IMPLIED: &[&str] = &[
+            "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc"
+        ];
+        while self.current_node_is(IMPLIED) &&
+            !except.is_some_and(|name| self.current_node_is(&[name])) {
+            self.open_elements.pop();
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#generate-all-implied-end-tags-thoroughly
+    fn generate_all_implied_end_tags_thoroughly(&mut self) {
+        const IMPLIED: &[&str] = &[
+            "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb",
+            "rp", "rt", "rtc", "tbody", "td", "tfoot", "th", "thead", "tr"
+        ];
+        while self.current_node_is(IMPLIED) {
+            self.open_elements.pop();
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#special
+    fn is_special(&self, node_ref: ArenaRef) -> bool {
+        if let Some(name) = self.html_name_of(node_ref) {
+            return matches!(name,
+                "address" | "applet" | "area" | "article" | "aside" | "base" |
+                "basefont" | "bgsound" | "blockquote" | "body" | "br" | "button" |
+                "caption" | "center" | "col" | "colgroup" | "dd" | "details" |
+                "dir" | "div" | "dl" | "dt" | "embed" | "fieldset" | "figcaption" |
+                "figure" | "footer" | "form" | "frame" | "frameset" | "h1" | "h2" |
+                "h3" | "h4" | "h5" | "h6" | "head" | "header" | "hgroup" | "hr" |
+                "html" | "iframe" | "img" | "input" | "keygen" | "li" | "link" |
+                "listing" | "main" | "marquee" | "menu" | "meta" | "nav" |
+                "noembed" | "noframes" | "noscript" | "object" | "ol" | "p" |
+                "param" | "plaintext" | "pre" | "script" | "search" | "section" |
+                "select" | "source" | "style" | "summary" | "table" | "tbody" |
+                "td" | "template" | "textarea" | "tfoot" | "th" | "thead" |
+                "title" | "tr" | "track" | "ul" | "wbr" | "xmp");
+        }
+        self.element_of(node_ref)
+            .is_some_and(Self::is_special_foreign_element)
+    }
+
+    // the MathML and SVG elements that are special, these also bound every
+    // scope but the table and select ones
+    fn is_special_foreign_element(element: &Element) -> bool {
+        Self::is_mathml_text_integration_point(element) ||
+            (element.namespace() == Namespace::MathMl && element.name() == "annotation-xml") ||
+            (element.namespace() == Namespace::Svg &&
+                matches!(element.name(), "foreignObject" | "desc" | "title"))
+    }
+
+    // pops elements until one of the names has been popped, the Document
+    // at the bottom of the stack is never popped
+    fn pop_until_one_of(&mut self, names: &[&str]) {
+        while self.open_elements.len() > 1 {
+            let node = self.open_elements.pop().unwrap_or(0);
+            if self.is_one_of_html_elements(node, names) {
+                break;
+            }
+        }
+    }
+
+    fn pop_until(&mut self, name: &str) {
+        self.pop_until_one_of(&[name]);
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#close-a-p-element
+    // every caller only closes the p element if there's one in button scope,
+    // so the check is done here
+    fn close_p_element(&mut self) {
+        if !self.has_element_in_scope(&["p"], Scope::Button) {
+            return;
+        }
+        self.generate_implied_end_tags(Some("p"));
+        if !self.current_node_is(&["p"]) {
+            self.parse_error(ParseErrorKind::UnexpectedEndTag(String::from("p")));
+        }
+        self.pop_until("p");
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#clear-the-stack-back-to-a-table-context
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while self.open_elements.len() > 1 && !self.current_node_is(names) {
+            self.open_elements.pop();
+        }
+    }
+
+    // an element the markup left out, made as if its start tag had no
+    // attributes. It has no source of its own, so its span is empty at the
+    // token that implied it.
+    fn insert_implied_html_element(&mut self, name: &str) -> Result<ArenaRef, HTMLError> {
+        let implied_span = self.implied_span();
+        let token_span = mem::replace(&mut self.token_span, implied_span);
+        let result = self.insert_html_element(&Tag {
+            name: String::from(name),
+            ..Tag::default()
+        });
+        self.token_span = token_span;
+        result
+    }
+
+    fn implied_span(&self) -> Span {
+        Span::new(self.token_span.start, self.token_span.start)
+    }
+
+    fn add_missing_attributes(&mut self, node_ref: ArenaRef, tag: &Tag) {
+        if let Some(node) = self.dom_tree.arena.get_mut(node_ref) {
+            if let DomObject::Element(ref mut element) = node.dom_obj {
+                element.add_missing_attributes(&tag.attributes);
+            }
+        }
+    }
+
+    // anything left open other than these when the body ends is a parse
+    // error
+    fn has_unclosed_elements(&self) -> bool {
+        self.open_elements[1..].iter().any(|&node| {
+            !self.is_one_of_html_elements(node, &[
+                "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt",
+                "rtc", "tbody", "td", "tfoot", "th", "thead", "tr", "body", "html"
+            ])
+        })
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#reset-the-insertion-mode-appropriately
+    // there's no fragment parsing, so the last node is always the html element
+    fn reset_insertion_mode(&mut self) {
+        for idx in (1..self.open_elements.len()).rev() {
+            let node = self.open_elements[idx];
+            let last = idx == 1;
+            let mode = match self.html_name_of(node) {
+                Some("select") => {
+                    let in_table = self.open_elements[1..idx]
+                        .iter()
+                        .rev()
+                        .take_while(|&&ancestor| !self.is_html_element(ancestor, "template"))
+                        .any(|&ancestor| self.is_html_element(ancestor, "table"));
+                    if in_table {
+                        Mode::InSelectInTable
+                    } else {
+                        Mode::InSelect
+                    }
+                },
+                Some("td") | Some("th") if !last => Mode::InCell,
+                Some("tr") => Mode::InRow,
+                Some("tbody") | Some("thead") | Some("tfoot") => Mode::InTableBody,
+                Some("caption") => Mode::InCaption,
+                Some("colgroup") => Mode::InColumnGroup,
+                Some("table") => Mode::InTable,
+                Some("template") => match self.template_insertion_modes.last() {
+                    Some(&mode) => mode,
+                    None => continue,
+                },
+                Some("head") if !last => Mode::InHead,
+                Some("body") => Mode::InBody,
+                Some("frameset") => Mode::InFrameset,
+                Some("html") if self.dom_tree.get_head().is_none() => Mode::BeforeHead,
+                Some("html") => Mode::AfterHead,
+                _ if last => Mode::InBody,
+                _ => continue,
+            };
+            self.insertion_mode = mode;
+            return;
+        }
+        self.insertion_mode = Mode::InBody;
+    }
+
+    fn element_of(&self, node_ref: ArenaRef) -> Option<&Element> {
+        match self.dom_tree.arena.get(node_ref) {
+            Some(node) => match &node.dom_obj {
+                DomObject::Element(element) => Some(element),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    // the adjusted current node is always the current node, as the parser
+    // does not implement the fragment parsing algorithm
+    fn adjusted_current_node(&self) -> Option<&Element> {
+        self.open_elements
+            .last()
+            .and_then(|node_ref| self.element_of(*node_ref))
+    }
+
+    pub fn adjusted_current_node_is_foreign(&self) -> bool {
+        self.adjusted_current_node()
+            .is_some_and(|element| element.namespace() != Namespace::Html)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#mathml-text-integration-point
+    fn is_mathml_text_integration_point(element: &Element) -> bool {
+        element.namespace() == Namespace::MathMl &&
+            matches!(element.name(), "mi" | "mo" | "mn" | "ms" | "mtext")
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#html-integration-point
+    fn is_html_integration_point(element: &Element) -> bool {
+        match element.namespace() {
+            Namespace::MathMl => {
+                element.name() == "annotation-xml" &&
+                    element.get_attribute("encoding").is_some_and(|encoding| {
+                        encoding.eq_ignore_ascii_case("text/html") ||
+                            encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                    })
+            },
+            Namespace::Svg => matches!(element.name(), "foreignObject" | "desc" | "title"),
+            _ => false,
+        }
+    }
+
+    fn use_insertion_mode_for(&self, token: &Token) -> bool {
+        let element = match self.adjusted_current_node() {
+            Some(element) => element,
+            None => return true,
+        };
+        if element.namespace() == Namespace::Html {
+            return true;
+        }
+        match token {
+            Token::StartTag(tag) => {
+                (Self::is_mathml_text_integration_point(element) &&
+                    tag.name != "mglyph" &&
+                    tag.name != "malignmark") ||
+                (element.namespace() == Namespace::MathMl &&
+                    element.name() == "annotation-xml" &&
+                    tag.name == "svg") ||
+                Self::is_html_integration_point(element)
+            },
+            Token::Characters(_) => {
+                Self::is_mathml_text_integration_point(element) ||
+                    Self::is_html_integration_point(element)
+            },
+            Token::EndOfFile => true,
+            _ => false,
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+    fn foreign_content_ruleset(&mut self, token: Token) -> ParserResult {
+        match token {
+            Token::Characters(data) => {
+                for _ in data.matches('\0') {
+                    self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                }
+                if data.contains(|char| char != '\0' && !char_is_whitespacish(char)) {
+                    self.frameset_ok = false;
+                }
+                self.insert_or_merge_text_into_tree(
+                    data.replace('\0', "\u{FFFD}")
+                    )?;
+                Ok(None)
+            },
+            Token::Comment(data) => {
+                self.insert_comment(&data)?;
+                Ok(None)
+            },
+            Token::Doctype(_) => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                Ok(None)
+            },
+            Token::StartTag(ref tag) if Self::breaks_out_of_foreign_content(tag) => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag(tag.name.clone()));
+                while let Some(element) = self.adjusted_current_node() {
+                    if element.namespace() == Namespace::Html ||
+                        Self::is_mathml_text_integration_point(element) ||
+                        Self::is_html_integration_point(element) {
+                        break;
+                    }
+                    self.open_elements.pop();
+                }
+                self.insertion_mode_ruleset(token)
+            },
+            Token::StartTag(ref tag) => {
+                let namespace = self.adjusted_current_node()
+                    .map(|element| element.namespace())
+                    .unwrap_or(Namespace::Html);
+                self.insert_foreign_element(tag, namespace)?;
+                Ok(None)
+            },
+            Token::EndTag(ref tag) => {
+                let tag_name = tag.name.clone();
+                if self.adjusted_current_node()
+                    .is_some_and(|element| element.name().to_ascii_lowercase() != tag_name) {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag(tag_name.clone()));
+                }
+                let mut idx = self.open_elements.len() - 1;
+                loop {
+                    // the html element sits above the Document at index 1
+                    if idx <= 1 {
+                        return Ok(None);
+                    }
+                    let element = match self.element_of(self.open_elements[idx]) {
+                        Some(element) => element,
+                        None => return Ok(None),
+                    };
+                    if element.name().to_ascii_lowercase() == tag_name {
+                        self.open_elements.truncate(idx);
+                        return Ok(None);
+                    }
+                    idx -= 1;
+                    if self.element_of(self.open_elements[idx])
+                        .is_some_and(|element| element.namespace() == Namespace::Html) {
+                        return self.insertion_mode_ruleset(token);
+                    }
+                }
+            },
+            Token::EndOfFile => self.insertion_mode_ruleset(token),
+        }
+    }
+
+    fn breaks_out_of_foreign_content(tag: &Tag) -> bool {
+        match tag.name.clone().as_str() {
+            "b" | "big" | "blockquote" | "body" | "br" | "center" | "code" |
+            "dd" | "div" | "dl" | "dt" | "em" | "embed" | "h1" | "h2" | "h3" |
+            "h4" | "h5" | "h6" | "head" | "hr" | "i" | "img" | "li" |
+            "listing" | "menu" | "meta" | "nobr" | "ol" | "p" | "pre" |
+            "ruby" | "s" | "small" | "span" | "strong" | "strike" | "sub" |
+            "sup" | "table" | "tt" | "u" | "ul" | "var" => true,
+            "font" => {
+                tag.has_attribute("color") ||
+                    tag.has_attribute("face") ||
+                    tag.has_attribute("size")
+            },
+            _ => false,
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#insert-an-html-element
+    fn insert_html_element(&mut self, tag: &Tag) -> Result<ArenaRef, HTMLError> {
+        self.insert_into_tree(DomObject::Element(Element::new(
+            tag.name.clone(),
+            Namespace::Html,
+            tag.attributes.clone()
+        )))?;
+        self.open_elements.push(self.last_element);
+        Ok(self.last_element)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#insert-a-foreign-element
+    fn insert_foreign_element(
+        &mut self,
+        tag: &Tag,
+        namespace: Namespace) -> Result<ArenaRef, HTMLError> {
+        let mut name = tag.name.clone();
+        let mut attributes = tag.attributes.clone();
+        match namespace {
+            Namespace::MathMl => adjust_mathml_attributes(&mut attributes),
+            Namespace::Svg => {
+                name = adjust_svg_tag_name(name);
+                adjust_svg_attributes(&mut attributes);
+            },
+            _ => {},
+        }
+        adjust_foreign_attributes(&mut attributes);
+        self.insert_into_tree(DomObject::Element(
+            Element::new(name, namespace, attributes)
+        ))?;
+        if !tag.self_closing {
+            self.open_elements.push(self.last_element);
+        }
+        Ok(self.last_element)
+    }
+
+    fn document_ref(&self) -> Result<ArenaRef, HTMLError> {
+        self.dom_tree.get_root().ok_or(HTMLError::InaccessibleDomTreeNode)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#insert-a-comment
+    fn insert_comment(&mut self, data: &str) -> Result<(), HTMLError> {
+        self.insert_into_tree(DomObject::Comment(
+            String::from(data)
+        ))
+    }
+
+    fn insert_comment_into(
+        &mut self,
+        data: &str,
+        parent_ref: ArenaRef) -> Result<(), HTMLError> {
+        self.dom_tree.insert(
+            DomObject::Comment(String::from(data)),
+            parent_ref,
+            self.token_span
+        )?;
+        Ok(())
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node
+    // nothing is inserted into a template element itself, it goes into the
+    // template's contents instead
+    fn appropriate_place_for_inserting(
+        &self,
+        override_target: Option<ArenaRef>) -> InsertionLocation {
+        let location = self.adjusted_insertion_location(override_target);
+        match self.dom_tree.get_template_contents(location.parent) {
+            Some(contents) => InsertionLocation::inside(contents),
+            None => location,
+        }
+    }
+
+    // the insertion location before templates are taken into account,
+    // foster parenting moves it next to the last table
+    fn adjusted_insertion_location(
+        &self,
+        override_target: Option<ArenaRef>) -> InsertionLocation {
+        let target = override_target.unwrap_or(self.current_node());
+        if !self.foster_parenting ||
+            !self.is_one_of_html_elements(target, &["table", "tbody", "tfoot", "thead", "tr"]) {
+            return InsertionLocation::inside(target);
+        }
+        let last_of = |name| self.open_elements
+            .iter()
+            .rposition(|&node| self.is_html_element(node, name));
+        let last_template = last_of("template");
+        let last_table = match last_of("table") {
+            Some(last_table) if last_template.is_none_or(|idx| idx < last_table) => last_table,
+            _ => {
+                // without a table the template, or else the html element,
+                // takes the node
+                let idx = last_template.unwrap_or(1);
+                return InsertionLocation::inside(self.open_elements[idx]);
+            },
+        };
+        let table_ref = self.open_elements[last_table];
+        match self.dom_tree.get_parent_of(table_ref) {
+            Some(parent) => InsertionLocation {
+                parent,
+                before: Some(table_ref),
+            },
+            None => InsertionLocation::inside(self.open_elements[last_table - 1]),
+        }
+    }
+
+    fn insert_into_tree(&mut self, obj: DomObject) -> Result<(), HTMLError> {
+        let location = self.appropriate_place_for_inserting(None);
+        let new_ref = self.dom_tree.create(obj, self.token_span);
+        self.dom_tree.insert_before(new_ref, location.parent, location.before)?;
+        self.last_element = new_ref;
+        Ok(())
+    }
+
+    fn insert_or_merge_text_into_tree(&mut self, data: String) -> Result<(), HTMLError> {
+        let location = self.appropriate_place_for_inserting(None);
+        // the Document can't hold text
+        if location.parent == self.document_ref()? {
+            return Ok(());
+        }
+        let siblings = &self.dom_tree.arena
+            .get(location.parent)
+            .ok_or(HTMLError::InaccessibleDomTreeNode)?
+            .children;
+        // the node that would end up just before the text
+        let previous = match location.before {
+            Some(before) => siblings
+                .iter()
+                .position(|&child| child == before)
+                .and_then(|idx| idx.checked_sub(1))
+                .map(|idx| siblings[idx]),
+            None => siblings.last().copied(),
+        };
+        if let Some(child_ref) = previous {
+            if let Some(node) = self.dom_tree.arena.get_mut(child_ref) {
+                if let DomObject::Text(ref mut string) = node.dom_obj {
+                    string.push_str(&data);
+                    node.span.end = self.token_span.end;
+                    return Ok(());
+                }
+            }
+        }
+        self.insert_into_tree(DomObject::Text(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the tree built from input, in the format of the html5lib tests
+    fn parse(input: &str) -> String {
+        let mut parser = Parser::new(None);
+        parser.feed(input.as_bytes()).unwrap();
+        let (dom, _) = parser.finish().unwrap();
+        dom.present()
+    }
+
+    // the parse errors from input, as they are presented
+    fn errors(input: &str) -> Vec<String> {
+        let mut parser = Parser::new(None);
+        parser.feed(input.as_bytes()).unwrap();
+        let (_, errors) = parser.finish().unwrap();
+        errors.iter().map(|error| error.present()).collect()
+    }
+
+    // each node in document order, with the byte offsets its span covers
+    fn spans(input: &str) -> Vec<String> {
+        fn walk(dom: &DomTree, node_ref: ArenaRef, spans: &mut Vec<String>) {
+            let node = dom.arena.get(node_ref).unwrap();
+            let name = match &node.dom_obj {
+                DomObject::Element(element) => element.name(),
+                DomObject::Text(_) => "#text",
+                DomObject::Comment(_) => "#comment",
+                DomObject::DocumentType(_) => "!doctype",
+                _ => "#document",
+            };
+            spans.push(format!("{} {}..{}", name, node.span.start.offset, node.span.end.offset));
+            for &child in node.children.iter() {
+                walk(dom, child, spans);
+            }
+        }
+        let mut parser = Parser::new(None);
+        parser.feed(input.as_bytes()).unwrap();
+        let (dom, _) = parser.finish().unwrap();
+        let mut spans = Vec::new();
+        walk(&dom, dom.get_root().unwrap(), &mut spans);
+        // the Document itself doesn't come from the source
+        spans.remove(0);
+        spans
+    }
+
+    // the tree from input with scripting enabled, each script writing the
+    // next of the markup when it runs. The input is served as UTF-8 so it is
+    // parsed without waiting to sniff, and has to go on past each script for
+    // long enough that the tokenizer isn't waiting for lookahead.
+    fn parse_with_scripts(input: &str, written: &[&str]) -> String {
+        let mut parser = Parser::new(Some("utf-8"));
+        parser.set_scripting(true);
+        parser.feed(input.as_bytes()).unwrap();
+        let mut written = written.iter();
+        while parser.take_pending_script().is_some() {
+            if let Some(markup) = written.next() {
+                parser.write(markup).unwrap();
+            }
+            parser.resume().unwrap();
+        }
+        assert!(written.next().is_none());
+        let (dom, _) = parser.finish().unwrap();
+        dom.present()
+    }
+
+    // the tree and encoding from feeding the bytes in chunks of the given
+    // size
+    fn parse_chunked(
+        bytes: &[u8],
+        chunk_size: usize,
+        transport_encoding: Option<&str>) -> (String, Encoding) {
+        let mut parser = Parser::new(transport_encoding);
+        for chunk in bytes.chunks(chunk_size) {
+            parser.feed(chunk).unwrap();
+        }
+        let (dom, _) = parser.finish().unwrap();
+        (dom.present(), dom.encoding())
+    }
+
+    // a meta the prescan doesn't get to, so it is only found by the
+    // parser once some of the input has been decoded the wrong way
+    fn late_meta(meta: &str) -> Vec<u8> {
+        let mut bytes = format!("<!--{}-->", "x".repeat(1100)).into_bytes();
+        bytes.extend_from_slice(b"<p>\xe9");
+        bytes.extend_from_slice(meta.as_bytes());
+        bytes.extend_from_slice(b"<p>\xe9\xe0");
+        bytes
+    }
+
+    fn tree(lines: &[&str]) -> String {
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    #[test]
+    fn whitespace_after_head() {
+        assert_eq!(parse("<head></head> \n x"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   \" \n \"",
+            "|   <body>",
+            "|     \"x\"",
+        ]));
+    }
+
+    #[test]
+    fn whitespace_in_table() {
+        assert_eq!(parse("<table> \n <tr>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <table>",
+            "|       \" \n \"",
+            "|       <tbody>",
+            "|         <tr>",
+        ]));
+    }
+
+    #[test]
+    fn characters_in_table_text_are_foster_parented_whole() {
+        assert_eq!(parse("<table> x &amp; <tr> </table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     \" x & \"",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           \" \"",
+        ]));
+    }
+
+    #[test]
+    fn whitespace_between_table_text_runs() {
+        assert_eq!(parse("<table>x<tr> <td>y</table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     \"x\"",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           \" \"",
+            "|           <td>",
+            "|             \"y\"",
+        ]));
+    }
+
+    #[test]
+    fn restart_with_declared_encoding() {
+        let bytes = late_meta("<meta charset=windows-1251>");
+        let expected = parse_chunked(&bytes, bytes.len(), Some("windows-1251"));
+        assert_eq!(expected.1, Encoding::Windows1251);
+        assert!(expected.0.contains("\"\u{439}\u{430}\""));
+        assert_eq!(parse_chunked(&bytes, bytes.len(), None), expected);
+    }
+
+    #[test]
+    fn restart_with_declared_encoding_chunked() {
+        let bytes = late_meta("<meta charset=windows-1251>");
+        let expected = parse_chunked(&bytes, bytes.len(), Some("windows-1251"));
+        for chunk_size in [1, 7, 1000] {
+            assert_eq!(parse_chunked(&bytes, chunk_size, None), expected);
+        }
+    }
+
+    #[test]
+    fn unsupported_charset_falls_back_to_content_type() {
+        let bytes = late_meta(
+            "<meta charset=bogus http-equiv=content-type \
+            content=\"text/html; charset=windows-1251\">");
+        let expected = parse_chunked(&bytes, bytes.len(), Some("windows-1251"));
+        assert_eq!(parse_chunked(&bytes, bytes.len(), None), expected);
+        assert_eq!(parse_chunked(&bytes, 7, None), expected);
+    }
+
+    #[test]
+    fn foreign_attributes_are_adjusted() {
+        assert_eq!(
+            parse(
+                "<svg viewbox='0 0 1 1' xlink:href=a xml:lang=en xmlns:xlink=x>\
+                <path pathlength=1 definitionurl=b></svg>\
+                <math definitionurl=c xlink:href=d xmlns=e></math><p xlink:href=f>"
+            ),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <svg svg>",
+                "|       viewBox=\"0 0 1 1\"",
+                "|       xlink href=\"a\"",
+                "|       xml lang=\"en\"",
+                "|       xmlns xlink=\"x\"",
+                "|       <svg path>",
+                "|         pathLength=\"1\"",
+                "|         definitionurl=\"b\"",
+                "|     <math math>",
+                "|       definitionURL=\"c\"",
+                "|       xlink href=\"d\"",
+                "|       xmlns xmlns=\"e\"",
+                "|     <p>",
+                "|       xlink:href=\"f\"",
+            ]));
+    }
+
+    #[test]
+    fn initial_and_before_modes() {
+        assert_eq!(parse("<!DOCTYPE html><!--c--><html><!--d--><head></head>"), tree(&[
+            "| <!DOCTYPE html>",
+            "| <!-- c -->",
+            "| <html>",
+            "|   <!-- d -->",
+            "|   <head>",
+            "|   <body>",
+        ]));
+    }
+
+    #[test]
+    fn in_head_noscript() {
+        assert_eq!(parse("<head><noscript><link><p>x</noscript>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|     <noscript>",
+            "|       <link>",
+            "|   <body>",
+            "|     <p>",
+            "|       \"x\"",
+        ]));
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(parse("<title>a<b></title><script>x<y</script>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|     <title>",
+            "|       \"a<b>\"",
+            "|     <script>",
+            "|       \"x<y\"",
+            "|   <body>",
+        ]));
+    }
+
+    #[test]
+    fn after_head() {
+        assert_eq!(parse("<head></head><style>s</style>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|     <style>",
+            "|       \"s\"",
+            "|   <body>",
+        ]));
+    }
+
+    #[test]
+    fn after_body_and_after_after_body() {
+        assert_eq!(parse("<p>a</body><!--b--></html><!--c-->"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       \"a\"",
+            "|   <!-- b -->",
+            "| <!-- c -->",
+        ]));
+    }
+
+    #[test]
+    fn table_modes() {
+        assert_eq!(
+            parse("<table><caption>c<colgroup><col>\
+                </colgroup><tr><td>d<th>e</table>"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <table>",
+                "|       <caption>",
+                "|         \"c\"",
+                "|       <colgroup>",
+                "|         <col>",
+                "|       <tbody>",
+                "|         <tr>",
+                "|           <td>",
+                "|             \"d\"",
+                "|           <th>",
+                "|             \"e\"",
+            ]));
+    }
+
+    #[test]
+    fn in_select() {
+        assert_eq!(parse("<select><option>a<option>b<optgroup><option>c</select>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <select>",
+            "|       <option>",
+            "|         \"a\"",
+            "|       <option>",
+            "|         \"b\"",
+            "|       <optgroup>",
+            "|         <option>",
+            "|           \"c\"",
+        ]));
+    }
+
+    #[test]
+    fn in_select_in_table() {
+        assert_eq!(parse("<table><tr><td><select><option>a<td>b"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           <td>",
+            "|             <select>",
+            "|               <option>",
+            "|                 \"a\"",
+            "|           <td>",
+            "|             \"b\"",
+        ]));
+    }
+
+    #[test]
+    fn frameset_modes() {
+        assert_eq!(
+            parse("<frameset><frame></frameset><noframes>x\
+                </noframes><!--c-->"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <frameset>",
+                "|     <frame>",
+                "|   <noframes>",
+                "|     \"x\"",
+                "|   <!-- c -->",
+            ]));
+    }
+
+    #[test]
+    fn template_contents() {
+        assert_eq!(
+            parse("<template><p>a</p><td>b</template><table>\
+                <template><tr></template>x</table>"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|     <template>",
+                "|       content",
+                "|         <p>",
+                "|           \"a\"",
+                "|         \"b\"",
+                "|   <body>",
+                "|     \"x\"",
+                "|     <table>",
+                "|       <template>",
+                "|         content",
+                "|           <tr>",
+            ]));
+    }
+
+    #[test]
+    fn template_modes() {
+        assert_eq!(parse("<template><tr><td>a</td></tr></template>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|     <template>",
+            "|       content",
+            "|         <tr>",
+            "|           <td>",
+            "|             \"a\"",
+            "|   <body>",
+        ]));
+    }
+
+    #[test]
+    fn foreign_content() {
+        assert_eq!(
+            parse("<svg><foreignObject><p>a</p></foreignObject>\
+                <circle/></svg><math><mi><b>x</b></mi></math>"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <svg svg>",
+                "|       <svg foreignObject>",
+                "|         <p>",
+                "|           \"a\"",
+                "|       <svg circle>",
+                "|     <math math>",
+                "|       <math mi>",
+                "|         <b>",
+                "|           \"x\"",
+            ]));
+    }
+
+    #[test]
+    fn annotation_xml_integration_point() {
+        assert_eq!(
+            parse("<math><annotation-xml encoding=Text/HTML><p>h\
+                </p></annotation-xml><annotation-xml><p>n"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <math math>",
+                "|       <math annotation-xml>",
+                "|         encoding=\"Text/HTML\"",
+                "|         <p>",
+                "|           \"h\"",
+                "|       <math annotation-xml>",
+                "|     <p>",
+                "|       \"n\"",
+            ]));
+    }
+
+    #[test]
+    fn adoption_agency_misnested_formatting() {
+        assert_eq!(parse("<b><i></b></i>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <b>",
+            "|       <i>",
+        ]));
+    }
+
+    #[test]
+    fn adoption_agency_furthest_block() {
+        assert_eq!(parse("<a><p></a>x"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <a>",
+            "|     <p>",
+            "|       <a>",
+            "|       \"x\"",
+        ]));
+    }
+
+    #[test]
+    fn adoption_agency_moves_text() {
+        assert_eq!(parse("<b>1<p>2</b>3</p>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <b>",
+            "|       \"1\"",
+            "|     <p>",
+            "|       <b>",
+            "|         \"2\"",
+            "|       \"3\"",
+        ]));
+    }
+
+    #[test]
+    fn noahs_ark_clause() {
+        assert_eq!(parse("<p><b><b><b><b>x</p>y"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       <b>",
+            "|         <b>",
+            "|           <b>",
+            "|             <b>",
+            "|               \"x\"",
+            "|     <b>",
+            "|       <b>",
+            "|         <b>",
+            "|           \"y\"",
+        ]));
+    }
+
+    // only eight blocks are moved out of the b, the rest stay inside the
+    // last copy of it
+    #[test]
+    fn adoption_agency_outer_loop_limit() {
+        assert_eq!(
+            parse("<b><div><div><div><div><div><div>\
+                <div><div><div><div>x</b>y"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <b>",
+                "|     <div>",
+                "|       <b>",
+                "|       <div>",
+                "|         <b>",
+                "|         <div>",
+                "|           <b>",
+                "|           <div>",
+                "|             <b>",
+                "|             <div>",
+                "|               <b>",
+                "|               <div>",
+                "|                 <b>",
+                "|                 <div>",
+                "|                   <b>",
+                "|                   <div>",
+                "|                     <b>",
+                "|                       <div>",
+                "|                         <div>",
+                "|                           \"xy\"",
+            ]));
+    }
+
+    // after three formatting elements have been cloned, the b further up
+    // the stack is dropped from the list instead
+    #[test]
+    fn adoption_agency_inner_loop_limit() {
+        assert_eq!(parse("<a><b><i><u><s><div>x</a>y"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <a>",
+            "|       <b>",
+            "|         <i>",
+            "|           <u>",
+            "|             <s>",
+            "|     <i>",
+            "|       <u>",
+            "|         <s>",
+            "|           <div>",
+            "|             <a>",
+            "|               \"x\"",
+            "|             \"y\"",
+        ]));
+    }
+
+    #[test]
+    fn rawtext_and_plaintext_elements() {
+        assert_eq!(parse("<p><style>a</p></style><plaintext>b</plaintext><p>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       <style>",
+            "|         \"a</p>\"",
+            "|     <plaintext>",
+            "|       \"b</plaintext><p>\"",
+        ]));
+    }
+
+    #[test]
+    fn cdata_sections() {
+        assert_eq!(
+            parse("<svg><![CDATA[a<b>]]><g><![CDATA[]]></g></svg><![CDATA[c]]>"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <svg svg>",
+                "|       \"a<b>\"",
+                "|       <svg g>",
+                "|     <!-- [CDATA[c]] -->",
+            ]));
+    }
+
+    #[test]
+    fn multi_byte_text() {
+        assert_eq!(
+            parse("<p>\u{FC}\u{1F600}</p>\u{20AC}"),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <p>",
+                "|       \"\u{FC}\u{1F600}\"",
+                "|     \"\u{20AC}\"",
+            ]));
+    }
+
+    #[test]
+    fn errors_in_source_order() {
+        assert_eq!(errors("<p>\n</b><div a a>\r\n\u{E9}&amp x</p></div>"), vec![
+            "1:1 missing-doctype",
+            "2:1 unexpected-end-tag </b>",
+            "2:12 duplicate-attribute",
+            "3:6 missing-semicolon-after-character-reference",
+            "3:8 unexpected-end-tag </p>",
+        ]);
+        assert_eq!(errors("<table>x<td></table></tr>\u{1}"), vec![
+            "1:1 missing-doctype",
+            "1:9 unexpected-characters",
+            "1:9 unexpected-start-tag <td>",
+            "1:21 unexpected-end-tag </tr>",
+            "1:26 control-character-in-input-stream",
+        ]);
+    }
+
+    #[test]
+    fn errors_after_the_document() {
+        // the frameset is out of place both after the body and back in it
+        assert_eq!(
+            errors("<!DOCTYPE html><html><body></html><!DOCTYPE html><frameset>"),
+            vec![
+                "1:35 unexpected-doctype",
+                "1:50 unexpected-start-tag <frameset>",
+                "1:50 unexpected-start-tag <frameset>",
+            ]);
+    }
+
+    #[test]
+    fn node_spans() {
+        assert_eq!(spans("<!DOCTYPE html><title>a</title>\r\n<b>x<!--c-->y</b>"), vec![
+            "!doctype 0..15",
+            "html 15..15",
+            "head 15..15",
+            "title 15..22",
+            "#text 22..23",
+            "#text 31..33",
+            "body 33..33",
+            "b 33..36",
+            "#text 36..37",
+            "#comment 37..45",
+            "#text 45..46",
+        ]);
+    }
+
+    #[test]
+    fn doctype_keywords_across_chunks() {
+        for (input, doctype) in [
+            (
+                "<!DocType Html PuBlIc \"-//W3C//DTD HTML 4.01//EN\" 'x'>",
+                "| <!DOCTYPE html \"-//W3C//DTD HTML 4.01//EN\" \"x\">",
+            ),
+            ("<!dOCTYPE html sYsTeM 'y'>", "| <!DOCTYPE html \"\" \"y\">"),
+        ] {
+            let expected = tree(&[doctype, "| <html>", "|   <head>", "|   <body>"]);
+            for chunk_size in [1, 3, input.len()] {
+                assert_eq!(parse_chunked(input.as_bytes(), chunk_size, None).0, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_transport_encoding_still_prescans() {
+        // only the prescan sees a meta in a textarea
+        let bytes = b"<textarea><meta charset=windows-1251></textarea>\xE9";
+        let expected = parse_chunked(bytes, bytes.len(), Some("bogus"));
+        assert_eq!(expected.1, Encoding::Windows1251);
+        assert_eq!(parse_chunked(bytes, 1, Some("bogus")), expected);
+    }
+
+    #[test]
+    fn written_markup_comes_before_the_rest_of_the_input() {
+        // the second script is written by the first and runs once it is
+        // done, then the tag the first left open takes its attribute from
+        // the input
+        assert_eq!(
+            parse_with_scripts(
+                "<script>1</script> id=n>net, then the rest of the network input",
+                &["<b>w<script>2</script><i", "y"]),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|     <script>",
+                "|       \"1\"",
+                "|   <body>",
+                "|     <b>",
+                "|       \"w\"",
+                "|       <script>",
+                "|         \"2\"",
+                "|       \"y\"",
+                "|       <i>",
+                "|         id=\"n\"",
+                "|         \"net, then the rest of the network input\"",
+            ]));
+    }
+
+    #[test]
+    fn written_markup_without_a_script_is_dropped() {
+        let mut parser = Parser::new(Some("utf-8"));
+        parser.set_scripting(true);
+        parser.feed(b"<p>a").unwrap();
+        parser.write("<b>").unwrap();
+        let (dom, _) = parser.finish().unwrap();
+        assert_eq!(dom.present(), parse("<p>a"));
+    }
+
+    #[test]
+    fn character_reference_stops_at_the_insertion_point() {
+        assert_eq!(
+            parse_with_scripts(
+                "<body><script></script>in; and then the rest of the network input",
+                &["&not"]),
+            tree(&[
+                "| <html>",
+                "|   <head>",
+                "|   <body>",
+                "|     <script>",
+                "|     \"\u{AC}in; and then the rest of the network input\"",
+            ]));
+    }
+
+    #[test]
+    fn implied_html_head_and_body() {
+        assert_eq!(parse("<p>hello"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       \"hello\"",
+        ]));
+        // they get an empty span where the token that implied them starts
+        assert_eq!(spans("<p>hello"), vec![
+            "html 0..0",
+            "head 0..0",
+            "body 0..0",
+            "p 0..3",
+            "#text 3..8",
+        ]);
+        assert_eq!(spans("<!-- c -->hello"), vec![
+            "#comment 0..10",
+            "html 10..10",
+            "head 10..10",
+            "body 10..10",
+            "#text 10..15",
+        ]);
+    }
+
+    #[test]
+    fn implied_head_is_the_head_element() {
+        let mut parser = Parser::new(None);
+        parser.feed(b"<title>t</title>").unwrap();
+        let (dom, _) = parser.finish().unwrap();
+        let head = dom.get_head().unwrap();
+        match &dom.arena.get(head).unwrap().dom_obj {
+            DomObject::Element(element) => assert_eq!(element.name(), "head"),
+            _ => panic!("the head pointer isn't an element"),
+        }
+    }
+
+    #[test]
+    fn element_in_scope() {
+        // marquee is a scope boundary, so the div stays open
+        assert_eq!(parse("<div><marquee></div>x"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <div>",
+            "|       <marquee>",
+            "|         \"x\"",
+        ]));
+        // so is foreignObject, the end tag makes an empty p of its own
+        assert_eq!(parse("<p><svg><foreignObject></p>x"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       <svg svg>",
+            "|         <svg foreignObject>",
+            "|           <p>",
+            "|           \"x\"",
+        ]));
+    }
+
+    #[test]
+    fn element_in_list_item_scope() {
+        // a list is a boundary for li
+        assert_eq!(parse("<li>a<ul>b</li>c"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <li>",
+            "|       \"a\"",
+            "|       <ul>",
+            "|         \"bc\"",
+        ]));
+        assert_eq!(parse("<li>a<div>b</li>c"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <li>",
+            "|       \"a\"",
+            "|       <div>",
+            "|         \"b\"",
+            "|     \"c\"",
+        ]));
+    }
+
+    #[test]
+    fn element_in_button_scope() {
+        assert_eq!(parse("<p><button>a</p>b"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       <button>",
+            "|         \"a\"",
+            "|         <p>",
+            "|         \"b\"",
+        ]));
+    }
+
+    #[test]
+    fn element_in_table_scope() {
+        // each end tag only gets as far as the nearest table
+        assert_eq!(parse("<table><td><table><td>x</table>y</table>z"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           <td>",
+            "|             <table>",
+            "|               <tbody>",
+            "|                 <tr>",
+            "|                   <td>",
+            "|                     \"x\"",
+            "|             \"y\"",
+            "|     \"z\"",
+        ]));
+    }
+
+    #[test]
+    fn element_in_select_scope() {
+        assert_eq!(parse("<select><option>a<option>b</select>c"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <select>",
+            "|       <option>",
+            "|         \"a\"",
+            "|       <option>",
+            "|         \"b\"",
+            "|     \"c\"",
+        ]));
+        // a select inside one closes it
+        assert_eq!(parse("<select><optgroup><option>a<select>b"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <select>",
+            "|       <optgroup>",
+            "|         <option>",
+            "|           \"a\"",
+            "|     \"b\"",
+        ]));
+    }
+
+    #[test]
+    fn implied_end_tags() {
+        assert_eq!(parse("<p>a<div>b"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       \"a\"",
+            "|     <div>",
+            "|       \"b\"",
+        ]));
+        // dt is special, so the li doesn't look past it
+        assert_eq!(parse("<dd>a<dt>b<li>c"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <dd>",
+            "|       \"a\"",
+            "|     <dt>",
+            "|       \"b\"",
+            "|       <li>",
+            "|         \"c\"",
+        ]));
+        assert_eq!(parse("<ruby>a<rb>b<rt>c<rp>d</ruby>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <ruby>",
+            "|       \"a\"",
+            "|       <rb>",
+            "|         \"b\"",
+            "|       <rt>",
+            "|         \"c\"",
+            "|       <rp>",
+            "|         \"d\"",
+        ]));
+        assert_eq!(parse("<li><p>a</li>b"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <li>",
+            "|       <p>",
+            "|         \"a\"",
+            "|     \"b\"",
+        ]));
+        assert_eq!(parse("<p>a<option>b<option>c</p>d"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <p>",
+            "|       \"a\"",
+            "|       <option>",
+            "|         \"b\"",
+            "|       <option>",
+            "|         \"c\"",
+            "|     \"d\"",
+        ]));
+    }
+
+    #[test]
+    fn elements_in_table_are_foster_parented() {
+        assert_eq!(parse("<table><div>a</div><tr><td>b</table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <div>",
+            "|       \"a\"",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           <td>",
+            "|             \"b\"",
+        ]));
+        // only a hidden input is allowed in the table
+        assert_eq!(parse("<table><input type=hidden><input></table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <input>",
+            "|     <table>",
+            "|       <input>",
+            "|         type=\"hidden\"",
+        ]));
+    }
+
+    #[test]
+    fn foster_parented_text_merges_before_the_table() {
+        assert_eq!(parse("a<table>b<tr>c</table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     \"abc\"",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+        ]));
+    }
+
+    #[test]
+    fn foster_parenting_uses_the_innermost_table() {
+        assert_eq!(parse("<table><tr><td><table>x<tr></table></td></tr></table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           <td>",
+            "|             \"x\"",
+            "|             <table>",
+            "|               <tbody>",
+            "|                 <tr>",
+        ]));
+        // a template holds the table, so it gets the node
+        assert_eq!(parse("<template><table><b>x</table></template>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|     <template>",
+            "|       content",
+            "|         <b>",
+            "|           \"x\"",
+            "|         <table>",
+            "|   <body>",
+        ]));
+    }
+
+    #[test]
+    fn foster_parented_formatting_elements() {
+        assert_eq!(parse("<table><a>1<td>2</td>3</table>"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <a>",
+            "|       \"1\"",
+            "|     <a>",
+            "|       \"3\"",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           <td>",
+            "|             \"2\"",
+        ]));
+        assert_eq!(parse("<table><b><tr><td>aaa</td></tr>bbb</table>ccc"), tree(&[
+            "| <html>",
+            "|   <head>",
+            "|   <body>",
+            "|     <b>",
+            "|     <b>",
+            "|       \"bbb\"",
+            "|     <table>",
+            "|       <tbody>",
+            "|         <tr>",
+            "|           <td>",
+            "|             \"aaa\"",
+            "|     <b>",
+            "|       \"ccc\"",
+        ]));
     }
 }
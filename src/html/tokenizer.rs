@@ -1,11 +1,22 @@
+use std::collections::VecDeque;
 use crate::html::{
+    entities,
+    errors::{ParseError, ParseErrorKind},
     tokens::{TokenBuilder, Token, TokenVariant },
     HTMLError
 };
-use crate::stream::Stream;
+use crate::stream::{Position, Span, Stream};
 
-pub type TokenList<'stream> = Vec<Token<'stream>>;
-#[derive(Debug)]
+pub type TokenList = VecDeque<Token>;
+
+// the most any state looks past the character it is on, in bytes: a named
+// character reference and the character after it. Until the input is
+// finished the tokenizer waits for this much to be buffered, so a chunk
+// boundary can't change how anything is tokenized.
+const LOOKAHEAD: usize = entities::LONGEST_NAME + 8;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
 pub enum States {
     Data,
@@ -90,38 +101,581 @@ pub enum States {
     NumericCharacterReferenceEnd,
 }
 
-pub struct Tokenizer<'stream> {
-    stream: Stream<'stream>,
+pub struct Tokenizer {
+    stream: Stream,
     pub state: States,
+    // set by the parser when the adjusted current node is not in the HTML
+    // namespace, which is the only place CDATA sections are recognised
+    pub foreign_content: bool,
     return_state: States,
-    tokens: TokenList<'stream>,
-    builder: TokenBuilder<'stream>,
+    // tokens emitted by the step in progress
+    tokens: TokenList,
+    ready: VecDeque<(Token, Span)>,
+    token_start: Position,
+    builder: TokenBuilder,
+    temporary_buffer: String,
+    character_reference_code: u32,
+    // position of the character run_state is looking at, which is where
+    // parse errors are reported
+    char_position: Position,
+    // where the attribute name being built starts, a duplicate is reported
+    // there
+    attribute_start: Position,
+    errors: Vec<ParseError>,
+    // the end-of-file token has been handed out, there is nothing after it
+    done: bool,
 }
 
-impl<'stream> Tokenizer<'stream> {
-    pub fn new(data: &'stream Vec<u8>) -> Self {
+impl Tokenizer {
+    pub fn new() -> Self {
         Self {
-            stream: Stream::new(data),
+            stream: Stream::new(),
             state: States::Data,
+            foreign_content: false,
             return_state: States::Data,
             tokens: TokenList::new(),
+            ready: VecDeque::new(),
+            token_start: Position::start(),
             builder: TokenBuilder::default(),
+            temporary_buffer: String::new(),
+            character_reference_code: 0,
+            char_position: Position::start(),
+            attribute_start: Position::start(),
+            errors: Vec::new(),
+            done: false,
+        }
+    }
+
+    // a tokenizer over the whole of input, for using it on its own
+    pub fn with_input(input: &str) -> Self {
+        let mut tokenizer = Self::new();
+        tokenizer.push_input(input);
+        tokenizer.finish_input();
+        tokenizer
+    }
+
+    // iterates over tokens along with the source they came from
+    pub fn spanned(self) -> SpannedTokens {
+        SpannedTokens { tokenizer: self }
+    }
+
+    fn parse_error(&mut self, kind: ParseErrorKind) {
+        self.parse_error_at(kind, self.char_position);
+    }
+
+    fn parse_error_at(&mut self, kind: ParseErrorKind, position: Position) {
+        self.errors.push(ParseError::new(kind, position));
+    }
+
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        let mut errors = self.stream.take_errors();
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|error| error.position.offset);
+        errors
+    }
+
+    pub fn position(&self) -> Position {
+        self.stream.position()
+    }
+
+    pub fn push_input(&mut self, chars: &str) {
+        self.stream.push_str(chars);
+    }
+
+    pub fn finish_input(&mut self) {
+        self.stream.close();
+    }
+
+    // the end-of-file token has been handed out. Until then running out of
+    // tokens only means more input is needed.
+    pub fn is_finished(&self) -> bool {
+        self.done
+    }
+
+    pub fn insertion_point(&self) -> Option<usize> {
+        self.stream.insertion_point()
+    }
+
+    pub fn set_insertion_point(&mut self, insertion_point: Option<usize>) {
+        self.stream.set_insertion_point(insertion_point);
+    }
+
+    // just before the next input character
+    pub fn set_insertion_point_here(&mut self) {
+        let offset = self.stream.offset();
+        self.stream.set_insertion_point(Some(offset));
+    }
+
+    // markup written by a script, tokenized before anything that follows
+    // the insertion point
+    pub fn insert_input(&mut self, chars: &str) {
+        self.stream.insert_str(chars);
+    }
+
+    // nothing can be tokenized until more input arrives or it is finished.
+    // Tokenizing also stops at the insertion point, as whatever follows it
+    // has to wait for the script that is writing there. Markup before the
+    // insertion point is all there is for now, so it doesn't wait for the
+    // lookahead.
+    pub fn needs_input(&self) -> bool {
+        self.ready.is_empty() && (
+            self.stream.at_insertion_point() ||
+            self.stream.insertion_point().is_none() &&
+                !self.stream.is_closed() &&
+                self.stream.remaining() < LOOKAHEAD
+        )
+    }
+
+    // adjacent characters are coalesced into a single run so the parser
+    // doesn't have to deal with them one token at a time
+    fn emit_characters(&mut self, chars: &str) {
+        match self.tokens.back_mut() {
+            Some(Token::Characters(data)) => data.push_str(chars),
+            _ => self.tokens.push_back(Token::Characters(chars.to_string())),
+        }
+    }
+
+    fn emit_character(&mut self, char: char) {
+        match self.tokens.back_mut() {
+            Some(Token::Characters(data)) => data.push(char),
+            _ => self.tokens.push_back(Token::Characters(String::from(char))),
+        }
+    }
+
+    // emits the current character along with every following character
+    // the state would emit unchanged, i.e. up to the next of stop
+    fn emit_character_run(&mut self, char: char, stop: &[char]) {
+        self.emit_character(char);
+        let run = self.stream.consume_until(stop);
+        self.emit_characters(&run);
+    }
+
+    fn emit_str(&mut self, chars: &'static str) {
+        self.emit_characters(chars);
+    }
+
+    fn emit_replacement_character(&mut self) {
+        self.emit_str("\u{FFFD}");
+    }
+
+    fn emit_temporary_buffer(&mut self) {
+        let buffer = std::mem::take(&mut self.temporary_buffer);
+        self.emit_characters(&buffer);
+    }
+
+    fn temporary_buffer_is(&self, cmp: &str) -> bool {
+        self.temporary_buffer == cmp
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#rcdata-end-tag-name-state
+    // the RCDATA, RAWTEXT, script data and script data escaped end tag name
+    // states only differ in the state they fall back to
+    fn appropriate_end_tag_name(
+        &mut self,
+        char: char,
+        fallback: States
+        ) -> Result<Option<Token>, HTMLError> {
+        let appropriate = self.builder.check_tag_validitiy();
+        match char {
+            '\t' |
+            '\n'/* LF */ |
+            '\u{000C}' /* FF */ |
+            ' ' if appropriate => self.state = States::BeforeAttributeName,
+            '/' if appropriate => self.state = States::SelfClosingStartTag,
+            '>' if appropriate => {
+                self.state = States::Data;
+                return Ok(Some(self.builder.build()));
+            },
+            'a'..='z' | 'A'..='Z' => {
+                self.builder.tag.name.push(char.to_ascii_lowercase());
+                self.temporary_buffer.push(char);
+            },
+            _ => {
+                self.builder.clear();
+                self.emit_str("</");
+                self.emit_temporary_buffer();
+                self.state = fallback;
+                self.stream.reconsume();
+            }
+        }
+        Ok(None)
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#attribute-name-state
+    // the name is checked against the tag's other attributes on leaving the
+    // attribute name state
+    fn commit_attribute_name(&mut self) {
+        if !self.builder.commit_buffer_to_attr_name() {
+            self.parse_error_at(ParseErrorKind::DuplicateAttribute, self.attribute_start);
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#charref-in-attribute
+    fn charref_in_attribute(&self) -> bool {
+        matches!(
+            self.return_state,
+            States::AttributeValueDoubleQuoted |
+            States::AttributeValueSingleQuoted |
+            States::AttributeValueUnquoted
+        )
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#flush-code-points-consumed-as-a-character-reference
+    fn flush_temporary_buffer(&mut self) {
+        let buffer = std::mem::take(&mut self.temporary_buffer);
+        if self.charref_in_attribute() {
+            self.builder.buffer.push_str(&buffer);
+        } else {
+            self.emit_characters(&buffer);
+        }
+    }
+
+    //https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+    fn numeric_character_reference_end(&mut self) {
+        let mut code = self.character_reference_code;
+        if code == 0x00 {
+            self.parse_error(ParseErrorKind::NullCharacterReference);
+            code = 0xFFFD;
+        } else if code > 0x10FFFF {
+            self.parse_error(ParseErrorKind::CharacterReferenceOutsideUnicodeRange);
+            code = 0xFFFD;
+        } else if (0xD800..=0xDFFF).contains(&code) {
+            self.parse_error(ParseErrorKind::SurrogateCharacterReference);
+            code = 0xFFFD;
+        } else if entities::is_noncharacter(code) {
+            self.parse_error(ParseErrorKind::NoncharacterCharacterReference);
+        } else if code == 0x0D || (
+            entities::is_control(code) &&
+            !matches!(code, 0x09 | 0x0A | 0x0C | 0x20)
+        ) {
+            self.parse_error(ParseErrorKind::ControlCharacterReference);
+            if let Some(replacement) = entities::c1_replacement(code) {
+                code = replacement;
+            }
+        }
+        self.temporary_buffer.clear();
+        self.temporary_buffer.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+        self.flush_temporary_buffer();
+        self.state = self.return_state;
+    }
+
+    fn match_named_character_reference(&self) -> Option<(usize, &'static str)> {
+        let candidate = self.stream.peek_slice(entities::LONGEST_NAME);
+        for len in (1..=candidate.len()).rev() {
+            if !candidate.is_char_boundary(len) {
+                continue;
+            }
+            if let Some(value) = entities::lookup(&candidate[..len]) {
+                return Some((len, value));
+            }
+        }
+        None
+    }
+
+    pub fn get_next_token(&mut self) -> Result<Option<(Token, Span)>, HTMLError> {
+        if self.done || self.needs_input() {
+            return Ok(None);
+        }
+        if self.ready.is_empty() {
+            self.step()?;
+        }
+        match self.ready.pop_front() {
+            Some((Token::Characters(data), span)) => {
+                Ok(Some(self.coalesce_characters(data, span)?))
+            },
+            Some((Token::EndTag(tag), span)) => {
+                if !tag.attributes.is_empty() {
+                    self.parse_error_at(ParseErrorKind::EndTagWithAttributes, span.start);
+                }
+                if tag.self_closing {
+                    self.parse_error_at(ParseErrorKind::EndTagWithTrailingSolidus, span.start);
+                }
+                Ok(Some((Token::EndTag(tag), span)))
+            },
+            Some((Token::EndOfFile, span)) => {
+                self.done = true;
+                Ok(Some((Token::EndOfFile, span)))
+            },
+            token => Ok(token),
         }
     }
 
-    pub fn get_next_token(&mut self) -> Result<Option<Token>, HTMLError> {
-        if !self.tokens.is_empty() {
-            Ok(self.tokens.pop())
-        } else if self.stream.is_eof() {
-            // TODO: we need to handle EOF differnet for some states
-            Ok(Some(Token::EndOfFile))
+    // runs the state machine for one character (or the end of the file)
+    // and hands whatever it emitted over with the span of source it came
+    // from. A token spans from the end of the one before it, or from
+    // wherever the tokenizer last sat in a text state, to the current
+    // position.
+    fn step(&mut self) -> Result<(), HTMLError> {
+        if matches!(self.state,
+            States::Data |
+            States::RCData |
+            States::RawText |
+            States::ScriptData |
+            States::PlainText) {
+            self.token_start = self.stream.position();
+        }
+        let token = if self.stream.is_eof() {
+            self.run_eof_state()?
         } else {
-            println!("[TOKENIZER STATE:{:?}]", self.state);
-            self.run_state()
+            self.run_state()?
+        };
+        if let Some(token) = token {
+            self.tokens.push_back(token);
+        }
+        if self.tokens.is_empty() {
+            return Ok(());
+        }
+        let end = self.stream.position();
+        for token in self.tokens.drain(..) {
+            let span = match token {
+                Token::EndOfFile => Span::new(end, end),
+                _ => Span::new(self.token_start, end),
+            };
+            self.ready.push_back((token, span));
+        }
+        self.token_start = end;
+        Ok(())
+    }
+
+    // keeps the tokenizer going for as long as it only produces characters,
+    // so runs split up by character references and the like reach the
+    // parser as one token. Stops as soon as anything else is queued, as the
+    // parser may need to act on that before tokenizing continues.
+    fn coalesce_characters(
+        &mut self,
+        mut data: String,
+        mut span: Span) -> Result<(Token, Span), HTMLError> {
+        loop {
+            // the end of the file can still flush characters, such as a
+            // character reference it cut short
+            while self.ready.is_empty() && !self.needs_input() {
+                self.step()?;
+            }
+            match self.ready.front() {
+                Some((Token::Characters(_), _)) => {
+                    if let Some((Token::Characters(more), more_span)) = self.ready.pop_front() {
+                        data.push_str(&more);
+                        span.end = more_span.end;
+                    }
+                },
+                _ => break,
+            }
+        }
+        Ok((Token::Characters(data), span))
+    }
+
+    // every state has its own EOF rule, many of which flush whatever was being
+    // built before emitting the end-of-file token. States that reconsume in
+    // another state just switch and go around the loop again.
+    fn run_eof_state(&mut self) -> Result<Option<Token>, HTMLError> {
+        self.char_position = self.stream.position();
+        loop {
+            match self.state {
+                States::Data |
+                States::RCData |
+                States::RawText |
+                States::ScriptData |
+                States::PlainText => break,
+
+                States::TagOpen => {
+                    self.parse_error(ParseErrorKind::EofBeforeTagName);
+                    self.emit_str("<");
+                    break;
+                },
+                States::EndTagOpen => {
+                    self.parse_error(ParseErrorKind::EofBeforeTagName);
+                    self.emit_str("</");
+                    break;
+                },
+                States::TagName |
+                States::BeforeAttributeName |
+                States::AttributeName |
+                States::AfterAttributeName |
+                States::BeforeAttributeValue |
+                States::AttributeValueDoubleQuoted |
+                States::AttributeValueSingleQuoted |
+                States::AttributeValueUnquoted |
+                States::AfterAttributeValueQuoted |
+                States::SelfClosingStartTag => {
+                    self.parse_error(ParseErrorKind::EofInTag);
+                    self.builder.clear();
+                    break;
+                },
+
+                States::RCDataLessThanSign |
+                States::RawTextLessThanSign |
+                States::ScriptDataLessThanSign => {
+                    self.emit_str("<");
+                    break;
+                },
+                States::RCDataEndTagOpen |
+                States::RawTextEndTagOpen |
+                States::ScriptDataEndTagOpen => {
+                    self.emit_str("</");
+                    break;
+                },
+                States::RCDataEndTagName |
+                States::RawTextEndTagName |
+                States::ScriptDataEndTagName => {
+                    self.builder.clear();
+                    self.emit_str("</");
+                    self.emit_temporary_buffer();
+                    break;
+                },
+                States::ScriptDataEscapedLessThanSign => {
+                    self.emit_str("<");
+                    self.state = States::ScriptDataEscaped;
+                },
+                States::ScriptDataEscapedEndTagOpen => {
+                    self.emit_str("</");
+                    self.state = States::ScriptDataEscaped;
+                },
+                States::ScriptDataEscapedEndTagName => {
+                    self.builder.clear();
+                    self.emit_str("</");
+                    self.emit_temporary_buffer();
+                    self.state = States::ScriptDataEscaped;
+                },
+
+                States::ScriptDataEscapeStart |
+                States::ScriptDataEscapeStartDash => break,
+                States::ScriptDataEscaped |
+                States::ScriptDataEscapedDash |
+                States::ScriptDataEscapedDashDash |
+                States::ScriptDataDoubleEscapeStart |
+                States::ScriptDataDoubleEscaped |
+                States::ScriptDataDoubleEscapedDash |
+                States::ScriptDataDoubleEscapedDashDash |
+                States::ScriptDataDoubleEscapedLessThanSign |
+                States::ScriptDataDoubleEscapeEnd => {
+                    self.parse_error(ParseErrorKind::EofInScriptHtmlCommentLikeText);
+                    break;
+                },
+
+                States::MarkupDeclarationOpen => {
+                    self.parse_error(ParseErrorKind::IncorrectlyOpenedComment);
+                    self.builder.set_variant(TokenVariant::Comment)?;
+                    self.state = States::BogusComment;
+                },
+                States::BogusComment => {
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+                States::CommentStart |
+                States::CommentStartDash |
+                States::Comment |
+                States::CommentLessThanSign |
+                States::CommentLessThanSignBang |
+                States::CommentLessThanSignBangDash |
+                States::CommentLessThanSignBangDashDash |
+                States::CommentEndDash |
+                States::CommentEnd |
+                States::CommentEndBang => {
+                    self.parse_error(ParseErrorKind::EofInComment);
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+
+                States::DocType |
+                States::BeforeDocTypeName |
+                States::AfterDocTypeName |
+                States::AfterDocTypeNamePublicKeyword |
+                States::BeforeDocTypePublicIdentifier |
+                States::AfterDocTypePublicIdentifier |
+                States::BetweenDocTypePublicSystemIdentifiers |
+                States::AfterDocTypeSystemKeyword |
+                States::BeforeDocTypeSystemIdentifier |
+                States::AfterDocTypeSystemIdentifier => {
+                    self.parse_error(ParseErrorKind::EofInDoctype);
+                    self.builder.force_quirks();
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+                States::DocTypeName => {
+                    self.parse_error(ParseErrorKind::EofInDoctype);
+                    self.builder.commit_buffer_to_doctype_name();
+                    self.builder.force_quirks();
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+                States::DocTypePublicIdentifierDoubleQuoted |
+                States::DocTypePublicIdentifierSingleQuoted => {
+                    self.parse_error(ParseErrorKind::EofInDoctype);
+                    self.builder.commit_buffer_to_public_id();
+                    self.builder.force_quirks();
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+                States::DocTypeSystemIdentifierDoubleQuoted |
+                States::DocTypeSystemIdentifierSingleQuoted => {
+                    self.parse_error(ParseErrorKind::EofInDoctype);
+                    self.builder.commit_buffer_to_system_id();
+                    self.builder.force_quirks();
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+                States::BogusDocType => {
+                    let token = self.builder.build();
+                    self.tokens.push_back(token);
+                    break;
+                },
+
+                States::CDataSection => {
+                    self.parse_error(ParseErrorKind::EofInCdata);
+                    break;
+                },
+                States::CDataSectionBracket => {
+                    self.emit_str("]");
+                    self.state = States::CDataSection;
+                },
+                States::CDataSectionEnd => {
+                    self.emit_str("]]");
+                    self.state = States::CDataSection;
+                },
+
+                States::CharacterReference => {
+                    self.temporary_buffer.clear();
+                    self.temporary_buffer.push('&');
+                    self.flush_temporary_buffer();
+                    self.state = self.return_state;
+                },
+                States::NamedCharacterReference => {
+                    self.flush_temporary_buffer();
+                    self.state = self.return_state;
+                },
+                States::NumericCharacterReference |
+                States::HexadecimalCharacterReferenceStart |
+                States::DecimalCharacterReferenceStart => {
+                    self.parse_error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                    self.flush_temporary_buffer();
+                    self.state = self.return_state;
+                },
+                States::AmbiguousAmpersand => {
+                    self.state = self.return_state;
+                },
+                States::HexadecimalCharacterReference |
+                States::DecimalCharacterReference => {
+                    self.parse_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                    self.numeric_character_reference_end();
+                },
+                States::NumericCharacterReferenceEnd => {
+                    self.numeric_character_reference_end();
+                },
+            }
         }
+        self.state = States::Data;
+        self.tokens.push_back(Token::EndOfFile);
+        Ok(None)
     }
 
     pub fn run_state(&mut self) -> Result<Option<Token>, HTMLError> {
+        self.char_position = self.stream.position();
         let char = self.stream.current();
         self.stream.advance();
         match self.state {
@@ -129,118 +683,93 @@ impl<'stream> Tokenizer<'stream> {
             //https://html.spec.whatwg.org/multipage/parsing.html#data-state
             States::Data => {
                 match char {
-                    b'&' => {
+                    '&' => {
                         self.return_state = States::Data;
                         self.state = States::CharacterReference;
                     },
-                    b'<' => {
+                    '<' => {
                         self.state = States::TagOpen;
                     },
-                    b'\0' => {
-                        // TODO: log unexpected-null-character error
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(char);
-                        return Ok(Some(self.builder.build()));
-
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_character(char);
                     },
-                    _ => {
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(char);
-                        return Ok(Some(self.builder.build()));
-                    }
+                    _ => self.emit_character_run(char, &['&', '<', '\0']),
                 }
             },
 
             States::RCData => {
                 match char {
-                    b'&' => {
+                    '&' => {
                         self.return_state = States::RCData;
                         self.state = States::CharacterReference;
                     },
-                    b'<' => self.state = States::RCDataLessThanSign,
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.tag.name.push(&0xFF);
-                        self.builder.tag.name.push(&0xFD);
+                    '<' => self.state = States::RCDataLessThanSign,
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_replacement_character();
                     },
-                    _ => {
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(char);
-                        return Ok(Some(self.builder.build()));
-                    }
+                    _ => self.emit_character_run(char, &['&', '<', '\0']),
                 }
             },
             
             States::RawText => {
                 match char {
-                    b'<' => self.state = States::RawTextLessThanSign,
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.tag.name.push(&0xFF);
-                        self.builder.tag.name.push(&0xFD);
+                    '<' => self.state = States::RawTextLessThanSign,
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_replacement_character();
                     },
-                    _ => {
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(char);
-                        return Ok(Some(self.builder.build()));
-                    }
+                    _ => self.emit_character_run(char, &['<', '\0']),
                 }
             },
 
+            //https://html.spec.whatwg.org/multipage/parsing.html#script-data-state
             States::ScriptData => {
                 match char {
-                    b'<' => self.state = States::ScriptDataLessThanSign,
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.tag.name.push(&0xFF);
-                        self.builder.tag.name.push(&0xFD);
+                    '<' => self.state = States::ScriptDataLessThanSign,
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_replacement_character();
                     },
-                    _ => {
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(char);
-                        return Ok(Some(self.builder.build()));
-                    }
+                    _ => self.emit_character_run(char, &['<', '\0']),
                 }
             },
 
             States::PlainText => {
                 match char {
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.tag.name.push(&0xFF);
-                        self.builder.tag.name.push(&0xFD);
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_replacement_character();
                     },
-                    _ => {
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(char);
-                        return Ok(Some(self.builder.build()));
-                    }
+                    _ => self.emit_character_run(char, &['\0']),
                 }
             },
 
             //https://html.spec.whatwg.org/multipage/parsing.html#tag-open-state
             States::TagOpen => {
                 match char {
-                    b'!' => {
+                    '!' => {
                         self.state = States::MarkupDeclarationOpen;
                     },
-                    b'/' => {
+                    '/' => {
                         self.state = States::EndTagOpen;
                     },
-                    b'?' => {
+                    '?' => {
+                        self.parse_error(ParseErrorKind::UnexpectedQuestionMarkInsteadOfTagName);
                         self.state = States::BogusComment;
                         self.builder.set_variant(TokenVariant::Comment)?;
                         self.stream.reconsume();
                     },
-                    b'a'..=b'z' | b'A'..=b'Z' => {
+                    'a'..='z' | 'A'..='Z' => {
                         self.state = States::TagName;
                         self.builder.set_variant(TokenVariant::StartTag)?;
                         self.stream.reconsume();
                     },
                     _ => {
-                        // TODO: invalid-first-character-of-tag-name error
+                        self.parse_error(ParseErrorKind::InvalidFirstCharacterOfTagName);
                         self.state = States::Data;
-                        self.builder.set_variant(TokenVariant::Character)?;
+                        self.emit_character('<');
                         self.stream.reconsume();
                     }
                 }
@@ -248,17 +777,17 @@ impl<'stream> Tokenizer<'stream> {
 
             States::EndTagOpen => {
                 match char {
-                    b'a'..=b'z' | b'A'..=b'Z' => {
+                    'a'..='z' | 'A'..='Z' => {
                         self.state = States::TagName;
                         self.builder.set_variant(TokenVariant::EndTag)?;
                         self.stream.reconsume();
                     },
-                    b'>' => {
-                        // TODO: missing-end-tag-name error
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingEndTagName);
                         self.state = States::Data;
                     },
                     _ => {
-                        // TODO: invalid-first-character-of-tag-name error
+                        self.parse_error(ParseErrorKind::InvalidFirstCharacterOfTagName);
                         self.state = States::BogusComment;
                         self.builder.set_variant(TokenVariant::Comment)?;
                         self.stream.reconsume();
@@ -268,23 +797,21 @@ impl<'stream> Tokenizer<'stream> {
             
             States::TagName => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => { self.state = States::BeforeAttributeName; },
-                    b'/' => { self.state = States::SelfClosingStartTag; },
-                    b'>' => {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { self.state = States::BeforeAttributeName; },
+                    '/' => { self.state = States::SelfClosingStartTag; },
+                    '>' => {
                         self.state = States::Data;
                         return Ok(Some(self.builder.build()));
                     },
-                    b'A'..=b'Z' => {
-                        // TODO: handle lowercasing of tags during the tree creation
-                        self.builder.tag.name.push(char);
+                    'A'..='Z' => {
+                        self.builder.tag.name.push(char.to_ascii_lowercase());
                     },
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.tag.name.push(&0xFF);
-                        self.builder.tag.name.push(&0xFD);
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.tag.name.push('\u{FFFD}');
                     },
                     _ => {
                         self.builder.tag.name.push(char);
@@ -292,318 +819,1960 @@ impl<'stream> Tokenizer<'stream> {
                 }
             },
 
+            //https://html.spec.whatwg.org/multipage/parsing.html#rcdata-less-than-sign-state
             States::RCDataLessThanSign => {
                 match char {
-                    b'/' => {
+                    '/' => {
+                        self.temporary_buffer.clear();
                         self.state = States::RCDataEndTagOpen;
                     },
                     _ => {
-                        self.builder.set_variant(TokenVariant::Character)?;
-                        self.builder.buffer.push(&b'<');
+                        self.emit_str("<");
                         self.state = States::RCData;
                         self.stream.reconsume();
-                        return Ok(Some(self.builder.build()));
                     }
                 }
             },
             States::RCDataEndTagOpen => {
                 match char {
-                    b'a'..=b'z' | b'A'..=b'Z' => {
-                        self.builder.set_variant(TokenVariant::EndTag);
+                    'a'..='z' | 'A'..='Z' => {
+                        self.builder.set_variant(TokenVariant::EndTag)?;
                         self.state = States::RCDataEndTagName;
                         self.stream.reconsume();
                     },
-                    _ => todo!()
+                    _ => {
+                        self.emit_str("</");
+                        self.state = States::RCData;
+                        self.stream.reconsume();
+                    }
                 }
             },
             States::RCDataEndTagName => {
+                return self.appropriate_end_tag_name(char, States::RCData);
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#rawtext-less-than-sign-state
+            States::RawTextLessThanSign => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => {
-                        if self.builder.check_tag_validitiy() {
-                            self.state = States::BeforeAttributeName;
-                        } else {
-                            todo!()
-                        }
-                    },
-                    b'/' => todo!(),
-                    b'>' => {
-                        if self.builder.check_tag_validitiy() {
-                            self.state = States::Data;
-                            return Ok(Some(self.builder.build()));
-                        } else {
-                            todo!()
-                        }
+                    '/' => {
+                        self.temporary_buffer.clear();
+                        self.state = States::RawTextEndTagOpen;
                     },
-                    b'a'..=b'z' | b'A'..=b'Z' => {
-                        self.builder.tag.name.push(char);
-                    },
-                    _ => todo!()
+                    _ => {
+                        self.emit_str("<");
+                        self.state = States::RawText;
+                        self.stream.reconsume();
+                    }
                 }
             },
-            // RawTextLessThanSign,
-            // RawTextEndTagOpen,
-            // RawTextEndTagName,
-            States::BeforeAttributeName => {
+            States::RawTextEndTagOpen => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => { },
-                    b'/' |
-                    b'>' => {
-                        self.state = States::AfterAttributeName;
+                    'a'..='z' | 'A'..='Z' => {
+                        self.builder.set_variant(TokenVariant::EndTag)?;
+                        self.state = States::RawTextEndTagName;
                         self.stream.reconsume();
                     },
-                    b'=' => {
-                        // TODO: unexpected-equqls-sign-before-attribute-name error
-                        self.builder.buffer.push(char);
-                        self.state = States::AttributeName;
-                    },
                     _ => {
-                        self.state = States::AttributeName;
+                        self.emit_str("</");
+                        self.state = States::RawText;
                         self.stream.reconsume();
                     }
                 }
             },
+            States::RawTextEndTagName => {
+                return self.appropriate_end_tag_name(char, States::RawText);
+            },
 
-            States::AttributeName => {
+            //https://html.spec.whatwg.org/multipage/parsing.html#script-data-less-than-sign-state
+            States::ScriptDataLessThanSign => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => { },
-                    b'/' |
-                    b'>' => {
-                        self.state = States::AfterAttributeName;
-                        self.stream.reconsume();
-                    },
-                    b'=' => {
-                        self.builder.commit_buffer_to_attr_keys();
-                        self.state = States::BeforeAttributeValue;
-                    },
-                    b'A'..=b'Z' => {
-                        self.builder.buffer.push(char);
+                    '/' => {
+                        self.temporary_buffer.clear();
+                        self.state = States::ScriptDataEndTagOpen;
                     },
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.buffer.push(&0xFF);
-                        self.builder.buffer.push(&0xFD);
-                    },
-                    b'\''|
-                    b'"' |
-                    b'<' => {
-                        // TODO: unexpected-character-in-attribute-name errorname
-                        self.builder.buffer.push(char);
+                    '!' => {
+                        self.state = States::ScriptDataEscapeStart;
+                        self.emit_str("<!");
                     },
                     _ => {
-                        self.builder.buffer.push(char);
+                        self.emit_str("<");
+                        self.state = States::ScriptData;
+                        self.stream.reconsume();
                     }
                 }
             },
 
-            States::BeforeAttributeName => {
+            States::ScriptDataEndTagOpen => {
+                match char {
+                    'a'..='z' | 'A'..='Z' => {
+                        self.builder.set_variant(TokenVariant::EndTag)?;
+                        self.state = States::ScriptDataEndTagName;
+                        self.stream.reconsume();
+                    },
+                    _ => {
+                        self.emit_str("</");
+                        self.state = States::ScriptData;
+                        self.stream.reconsume();
+                    }
+                }
             },
-            States::AfterAttributeName => {
+
+            States::ScriptDataEndTagName => {
+                return self.appropriate_end_tag_name(char, States::ScriptData);
             },
-            States::BeforeAttributeValue => {
+
+            States::ScriptDataEscapeStart => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => {  },
-                    b'"' => self.state = States::AttributeValueDoubleQuoted,
-                    b'\'' => self.state = States::AttributeValueSingleQuoted,
-                    b'>' => {
-                        // TODO: missing-attribute-value error
-                        self.state = States::Data;
-                        return Ok(Some(self.builder.build()));
+                    '-' => {
+                        self.state = States::ScriptDataEscapeStartDash;
+                        self.emit_character(char);
                     },
                     _ => {
-                        self.state = States::AttributeValueUnquoted;
+                        self.state = States::ScriptData;
                         self.stream.reconsume();
                     }
                 }
             },
-            States::AttributeValueDoubleQuoted => {
+
+            States::ScriptDataEscapeStartDash => {
                 match char {
-                    b'"' => {
-                        self.state = States::AfterAttributeValueQuoted;
-                        self.builder.commit_buffer_to_attr_value();
+                    '-' => {
+                        self.state = States::ScriptDataEscapedDashDash;
+                        self.emit_character(char);
                     },
-                    b'&' => {
-                        self.return_state = States::AttributeValueDoubleQuoted;
-                        self.state = States::CharacterReference;
-                    },
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.buffer.push(&0xFF);
-                        self.builder.buffer.push(&0xFD);
-                    },
-                    _ => self.builder.buffer.push(char)
+                    _ => {
+                        self.state = States::ScriptData;
+                        self.stream.reconsume();
+                    }
                 }
-            }
-            States::AttributeValueUnquoted => {
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#script-data-escaped-state
+            States::ScriptDataEscaped => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => self.state = States::BeforeAttributeName,
-                    b'&' => {
-                        self.return_state = States::AttributeValueUnquoted;
-                        self.state = States::CharacterReference;
-                    },
-                    b'>' => {
-                        self.state = States::Data;
-                        self.builder.commit_buffer_to_attr_value();
-                        return Ok(Some(self.builder.build()));
+                    '-' => {
+                        self.state = States::ScriptDataEscapedDash;
+                        self.emit_character(char);
                     },
-                    b'"' |
-                    b'\''|
-                    b'<' |
-                    b'=' |
-                    b'`' => {
-                        // TODO: unexpected-character-in-unquoted-attribute-value error
-                        self.builder.push_to_buffer(char);
+                    '<' => self.state = States::ScriptDataEscapedLessThanSign,
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_replacement_character();
                     },
-                    _ => { self.builder.push_to_buffer(char); }
+                    _ => self.emit_character(char),
                 }
             },
-            States::AfterAttributeValueQuoted => {
+
+            States::ScriptDataEscapedDash => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => self.state = States::BeforeAttributeName,
-                    b'/' => self.state = States::SelfClosingStartTag,
-                    b'>' => {
-                        self.state = States::Data;
-                        return Ok(Some(self.builder.build()));
+                    '-' => {
+                        self.state = States::ScriptDataEscapedDashDash;
+                        self.emit_character(char);
+                    },
+                    '<' => self.state = States::ScriptDataEscapedLessThanSign,
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.state = States::ScriptDataEscaped;
+                        self.emit_replacement_character();
                     },
                     _ => {
-                        // TODO: missing-whitespace-between-attributes error
-                        self.state = States::BeforeAttributeName;
-                        self.stream.reconsume();
+                        self.state = States::ScriptDataEscaped;
+                        self.emit_character(char);
                     }
                 }
-            }
+            },
 
-            // AfterAttributeValueQuoted,
-            // SelfClosingStartTag,
-            // BogusComment,
-            States::MarkupDeclarationOpen => {
+            States::ScriptDataEscapedDashDash => {
                 match char {
-                    b'-' => {
-                        if self.stream.expect("-") {
-                            self.stream.consume("--");
-                            self.state = States::Comment;
-                        } else {
-                            todo!()
-                        }
-                    },
-                    b'd' | b'D' => {
-                        if self.stream.expect("OCTYPE") {
-                            self.stream.consume("doctype");
-                            self.state = States::DocType;
-                        } else {
-                            todo!()
-                        }
+                    '-' => self.emit_character(char),
+                    '<' => self.state = States::ScriptDataEscapedLessThanSign,
+                    '>' => {
+                        self.state = States::ScriptData;
+                        self.emit_character(char);
                     },
-                    b'[' => {
-                        if self.stream.expect("CDATA[") {
-                            todo!()
-                        } else {
-                            todo!()
-                        }
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.state = States::ScriptDataEscaped;
+                        self.emit_replacement_character();
                     },
-                    _ => { todo!() }
+                    _ => {
+                        self.state = States::ScriptDataEscaped;
+                        self.emit_character(char);
+                    }
                 }
             },
-            States::DocType => {
+
+            States::ScriptDataEscapedLessThanSign => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => self.state = States::BeforeDocTypeName,
-                    b'>' => {
+                    '/' => {
+                        self.temporary_buffer.clear();
+                        self.state = States::ScriptDataEscapedEndTagOpen;
+                    },
+                    'a'..='z' | 'A'..='Z' => {
+                        self.temporary_buffer.clear();
+                        self.emit_str("<");
+                        self.state = States::ScriptDataDoubleEscapeStart;
                         self.stream.reconsume();
-                        self.state = States::BeforeDocTypeName;
                     },
                     _ => {
-                        // TODO: missing-whitesspace-before-doctype-name error
+                        self.emit_str("<");
+                        self.state = States::ScriptDataEscaped;
                         self.stream.reconsume();
-                        self.state = States::BeforeDocTypeName;
                     }
                 }
             },
-            States::BeforeDocTypeName => {
+
+            States::ScriptDataEscapedEndTagOpen => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => { /* ignore */  },
-                    b'A'..=b'Z' => {
-                        self.builder.push_to_buffer(char);
-                        self.state = States::DocTypeName;
-                    },
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        todo!()
-                    },
-                    b'>' => {
-                        // TODO: missing-doctype-name error
-                        self.builder.force_quirks();
-                        self.state = States::Data;
-                        return Ok(Some(self.builder.build()));
+                    'a'..='z' | 'A'..='Z' => {
+                        self.builder.set_variant(TokenVariant::EndTag)?;
+                        self.state = States::ScriptDataEscapedEndTagName;
+                        self.stream.reconsume();
                     },
                     _ => {
-                        self.builder.push_to_buffer(char);
-                        self.state = States::DocTypeName;
+                        self.emit_str("</");
+                        self.state = States::ScriptDataEscaped;
+                        self.stream.reconsume();
                     }
                 }
+            },
 
+            States::ScriptDataEscapedEndTagName => {
+                return self.appropriate_end_tag_name(char, States::ScriptDataEscaped);
             },
-            States::DocTypeName => {
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#script-data-double-escape-start-state
+            States::ScriptDataDoubleEscapeStart => {
                 match char {
-                    b'\t' |
-                    b'\n'/* LF */ |
-                    0x0C /* FF */ |
-                    b' ' => self.state = States::AfterDocTypeName,
-                    b'>' => {
-                        self.state = States::Data;
-                        self.builder.set_variant(TokenVariant::Doctype)?;
-                        self.builder.commit_buffer_to_doctype_name();
-                        return Ok(Some(self.builder.build()));
-                    },
-                    b'A'..=b'Z' => {
-                        self.builder.push_to_buffer(char);
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' |
+                    '/' |
+                    '>' => {
+                        if self.temporary_buffer_is("script") {
+                            self.state = States::ScriptDataDoubleEscaped;
+                        } else {
+                            self.state = States::ScriptDataEscaped;
+                        }
+                        self.emit_character(char);
                     },
-                    b'\0' => {
-                        // TODO: unexpected-null-character error
-                        self.builder.push_replacement_character_to_buffer();
+                    'a'..='z' | 'A'..='Z' => {
+                        self.temporary_buffer.push(char.to_ascii_lowercase());
+                        self.emit_character(char);
                     },
                     _ => {
-                        self.builder.push_to_buffer(char);
+                        self.state = States::ScriptDataEscaped;
+                        self.stream.reconsume();
                     }
                 }
             },
-            // AfterDocTypeName,
-            // AfterDocTypeNamePublicKeyword,
-            // BeforeDocTypePublicIdentifier,
-            // DocTypePublicIdentifierDoubleQuoted,
-            // DocTypePublicIdentifierSingleQuoted,
-            // AfterDocTypePublicIdentifier,
-            // BetweenDocTypePublicSystemIdentifiers,
-            // AfterDocTypeSystemKeyword,
-            // BeforeDocTypeSystemIdentifier,
-            // DocTypeSystemIdentifierDoubleQuoted,
-            // DocTypeSystemIdentifierSingleQuoted,
-            // AfterDocTypeSystemIdentifier,
-            // BogusDocType,
-            _ => todo!(),
+
+            States::ScriptDataDoubleEscaped => {
+                match char {
+                    '-' => {
+                        self.state = States::ScriptDataDoubleEscapedDash;
+                        self.emit_character(char);
+                    },
+                    '<' => {
+                        self.state = States::ScriptDataDoubleEscapedLessThanSign;
+                        self.emit_character(char);
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.emit_replacement_character();
+                    },
+                    _ => self.emit_character(char),
+                }
+            },
+
+            States::ScriptDataDoubleEscapedDash => {
+                match char {
+                    '-' => {
+                        self.state = States::ScriptDataDoubleEscapedDashDash;
+                        self.emit_character(char);
+                    },
+                    '<' => {
+                        self.state = States::ScriptDataDoubleEscapedLessThanSign;
+                        self.emit_character(char);
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.state = States::ScriptDataDoubleEscaped;
+                        self.emit_replacement_character();
+                    },
+                    _ => {
+                        self.state = States::ScriptDataDoubleEscaped;
+                        self.emit_character(char);
+                    }
+                }
+            },
+
+            States::ScriptDataDoubleEscapedDashDash => {
+                match char {
+                    '-' => self.emit_character(char),
+                    '<' => {
+                        self.state = States::ScriptDataDoubleEscapedLessThanSign;
+                        self.emit_character(char);
+                    },
+                    '>' => {
+                        self.state = States::ScriptData;
+                        self.emit_character(char);
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.state = States::ScriptDataDoubleEscaped;
+                        self.emit_replacement_character();
+                    },
+                    _ => {
+                        self.state = States::ScriptDataDoubleEscaped;
+                        self.emit_character(char);
+                    }
+                }
+            },
+
+            States::ScriptDataDoubleEscapedLessThanSign => {
+                match char {
+                    '/' => {
+                        self.temporary_buffer.clear();
+                        self.state = States::ScriptDataDoubleEscapeEnd;
+                        self.emit_character(char);
+                    },
+                    _ => {
+                        self.state = States::ScriptDataDoubleEscaped;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::ScriptDataDoubleEscapeEnd => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' |
+                    '/' |
+                    '>' => {
+                        if self.temporary_buffer_is("script") {
+                            self.state = States::ScriptDataEscaped;
+                        } else {
+                            self.state = States::ScriptDataDoubleEscaped;
+                        }
+                        self.emit_character(char);
+                    },
+                    'a'..='z' | 'A'..='Z' => {
+                        self.temporary_buffer.push(char.to_ascii_lowercase());
+                        self.emit_character(char);
+                    },
+                    _ => {
+                        self.state = States::ScriptDataDoubleEscaped;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+            States::BeforeAttributeName => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { },
+                    '/' |
+                    '>' => {
+                        self.state = States::AfterAttributeName;
+                        self.stream.reconsume();
+                    },
+                    '=' => {
+                        self.parse_error(ParseErrorKind::UnexpectedEqualsSignBeforeAttributeName);
+                        self.builder.buffer.push(char);
+                        self.attribute_start = self.char_position;
+                        self.state = States::AttributeName;
+                    },
+                    _ => {
+                        self.attribute_start = self.char_position;
+                        self.state = States::AttributeName;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::AttributeName => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' |
+                    '/' |
+                    '>' => {
+                        self.commit_attribute_name();
+                        self.state = States::AfterAttributeName;
+                        self.stream.reconsume();
+                    },
+                    '=' => {
+                        self.commit_attribute_name();
+                        self.state = States::BeforeAttributeValue;
+                    },
+                    'A'..='Z' => {
+                        self.builder.buffer.push(char.to_ascii_lowercase());
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    '\''|
+                    '"' |
+                    '<' => {
+                        self.parse_error(ParseErrorKind::UnexpectedCharacterInAttributeName);
+                        self.builder.buffer.push(char);
+                    },
+                    _ => {
+                        self.builder.buffer.push(char);
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#after-attribute-name-state
+            States::AfterAttributeName => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { },
+                    '/' => self.state = States::SelfClosingStartTag,
+                    '=' => self.state = States::BeforeAttributeValue,
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.attribute_start = self.char_position;
+                        self.state = States::AttributeName;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+            States::BeforeAttributeValue => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => {  },
+                    '"' => self.state = States::AttributeValueDoubleQuoted,
+                    '\'' => self.state = States::AttributeValueSingleQuoted,
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingAttributeValue);
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.state = States::AttributeValueUnquoted;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+            States::AttributeValueDoubleQuoted => {
+                match char {
+                    '"' => {
+                        self.state = States::AfterAttributeValueQuoted;
+                        self.builder.commit_buffer_to_attr_value();
+                    },
+                    '&' => {
+                        self.return_state = States::AttributeValueDoubleQuoted;
+                        self.state = States::CharacterReference;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    _ => self.builder.buffer.push(char)
+                }
+            }
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#attribute-value-(single-quoted)-state
+            States::AttributeValueSingleQuoted => {
+                match char {
+                    '\'' => {
+                        self.state = States::AfterAttributeValueQuoted;
+                        self.builder.commit_buffer_to_attr_value();
+                    },
+                    '&' => {
+                        self.return_state = States::AttributeValueSingleQuoted;
+                        self.state = States::CharacterReference;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    _ => self.builder.buffer.push(char)
+                }
+            }
+            States::AttributeValueUnquoted => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => {
+                        self.builder.commit_buffer_to_attr_value();
+                        self.state = States::BeforeAttributeName;
+                    },
+                    '&' => {
+                        self.return_state = States::AttributeValueUnquoted;
+                        self.state = States::CharacterReference;
+                    },
+                    '>' => {
+                        self.state = States::Data;
+                        self.builder.commit_buffer_to_attr_value();
+                        return Ok(Some(self.builder.build()));
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    '"' |
+                    '\''|
+                    '<' |
+                    '=' |
+                    '`' => {
+                        self.parse_error(ParseErrorKind::UnexpectedCharacterInUnquotedAttributeValue);
+                        self.builder.push_to_buffer(char);
+                    },
+                    _ => { self.builder.push_to_buffer(char); }
+                }
+            },
+            States::AfterAttributeValueQuoted => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => self.state = States::BeforeAttributeName,
+                    '/' => self.state = States::SelfClosingStartTag,
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceBetweenAttributes);
+                        self.state = States::BeforeAttributeName;
+                        self.stream.reconsume();
+                    }
+                }
+            }
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#self-closing-start-tag-state
+            States::SelfClosingStartTag => {
+                match char {
+                    '>' => {
+                        self.builder.tag.self_closing = true;
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedSolidusInTag);
+                        self.state = States::BeforeAttributeName;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            // BogusComment,
+            //https://html.spec.whatwg.org/multipage/parsing.html#markup-declaration-open-state
+            States::MarkupDeclarationOpen => {
+                self.stream.reconsume();
+                if self.stream.consume("--") {
+                    self.builder.set_variant(TokenVariant::Comment)?;
+                    self.state = States::CommentStart;
+                } else if self.stream.consume_ci("DOCTYPE") {
+                    self.builder.set_variant(TokenVariant::Doctype)?;
+                    self.state = States::DocType;
+                } else if self.foreign_content && self.stream.consume("[CDATA[") {
+                    self.state = States::CDataSection;
+                } else if self.stream.consume("[CDATA[") {
+                    self.parse_error(ParseErrorKind::CdataInHtmlContent);
+                    self.builder.set_variant(TokenVariant::Comment)?;
+                    self.builder.buffer.push_str("[CDATA[");
+                    self.state = States::BogusComment;
+                } else {
+                    self.parse_error(ParseErrorKind::IncorrectlyOpenedComment);
+                    self.builder.set_variant(TokenVariant::Comment)?;
+                    self.state = States::BogusComment;
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#bogus-comment-state
+            States::BogusComment => {
+                match char {
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    _ => self.builder.push_to_buffer(char),
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#comment-start-state
+            States::CommentStart => {
+                match char {
+                    '-' => self.state = States::CommentStartDash,
+                    '>' => {
+                        self.parse_error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CommentStartDash => {
+                match char {
+                    '-' => self.state = States::CommentEnd,
+                    '>' => {
+                        self.parse_error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.builder.push_to_buffer('-');
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#comment-state
+            States::Comment => {
+                match char {
+                    '<' => {
+                        self.builder.push_to_buffer(char);
+                        self.state = States::CommentLessThanSign;
+                    },
+                    '-' => self.state = States::CommentEndDash,
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    _ => self.builder.push_to_buffer(char),
+                }
+            },
+
+            States::CommentLessThanSign => {
+                match char {
+                    '!' => {
+                        self.builder.push_to_buffer(char);
+                        self.state = States::CommentLessThanSignBang;
+                    },
+                    '<' => self.builder.push_to_buffer(char),
+                    _ => {
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CommentLessThanSignBang => {
+                match char {
+                    '-' => self.state = States::CommentLessThanSignBangDash,
+                    _ => {
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CommentLessThanSignBangDash => {
+                match char {
+                    '-' => self.state = States::CommentLessThanSignBangDashDash,
+                    _ => {
+                        self.state = States::CommentEndDash;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CommentLessThanSignBangDashDash => {
+                match char {
+                    '>' => {
+                        self.state = States::CommentEnd;
+                        self.stream.reconsume();
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::NestedComment);
+                        self.state = States::CommentEnd;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CommentEndDash => {
+                match char {
+                    '-' => self.state = States::CommentEnd,
+                    _ => {
+                        self.builder.push_to_buffer('-');
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#comment-end-state
+            States::CommentEnd => {
+                match char {
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    '!' => self.state = States::CommentEndBang,
+                    '-' => self.builder.push_to_buffer(char),
+                    _ => {
+                        self.builder.buffer.push_str("--");
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CommentEndBang => {
+                match char {
+                    '-' => {
+                        self.builder.buffer.push_str("--!");
+                        self.state = States::CommentEndDash;
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::IncorrectlyClosedComment);
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.builder.buffer.push_str("--!");
+                        self.state = States::Comment;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#doctype-state
+            States::DocType => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => self.state = States::BeforeDocTypeName,
+                    '>' => {
+                        self.stream.reconsume();
+                        self.state = States::BeforeDocTypeName;
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceBeforeDoctypeName);
+                        self.stream.reconsume();
+                        self.state = States::BeforeDocTypeName;
+                    }
+                }
+            },
+            States::BeforeDocTypeName => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { /* ignore */  },
+                    'A'..='Z' => {
+                        self.builder.push_to_buffer(char.to_ascii_lowercase());
+                        self.state = States::DocTypeName;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                        self.state = States::DocTypeName;
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingDoctypeName);
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.builder.push_to_buffer(char);
+                        self.state = States::DocTypeName;
+                    }
+                }
+
+            },
+            States::DocTypeName => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => {
+                        self.builder.commit_buffer_to_doctype_name();
+                        self.state = States::AfterDocTypeName;
+                    },
+                    '>' => {
+                        self.state = States::Data;
+                        self.builder.commit_buffer_to_doctype_name();
+                        return Ok(Some(self.builder.build()));
+                    },
+                    'A'..='Z' => {
+                        self.builder.push_to_buffer(char.to_ascii_lowercase());
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    _ => {
+                        self.builder.push_to_buffer(char);
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#after-doctype-name-state
+            States::AfterDocTypeName => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { /* ignore */ },
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.stream.reconsume();
+                        if self.stream.consume_ci("PUBLIC") {
+                            self.state = States::AfterDocTypeNamePublicKeyword;
+                        } else if self.stream.consume_ci("SYSTEM") {
+                            self.state = States::AfterDocTypeSystemKeyword;
+                        } else {
+                            self.parse_error(ParseErrorKind::InvalidCharacterSequenceAfterDoctypeName);
+                            self.builder.force_quirks();
+                            self.state = States::BogusDocType;
+                        }
+                    }
+                }
+            },
+
+            States::AfterDocTypeNamePublicKeyword => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => self.state = States::BeforeDocTypePublicIdentifier,
+                    '"' => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceAfterDoctypePublicKeyword);
+                        self.state = States::DocTypePublicIdentifierDoubleQuoted;
+                    },
+                    '\'' => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceAfterDoctypePublicKeyword);
+                        self.state = States::DocTypePublicIdentifierSingleQuoted;
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingDoctypePublicIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypePublicIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::BeforeDocTypePublicIdentifier => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { /* ignore */ },
+                    '"' => {
+                        self.state = States::DocTypePublicIdentifierDoubleQuoted;
+                    },
+                    '\'' => {
+                        self.state = States::DocTypePublicIdentifierSingleQuoted;
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingDoctypePublicIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypePublicIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::DocTypePublicIdentifierDoubleQuoted => {
+                match char {
+                    '"' => {
+                        self.builder.commit_buffer_to_public_id();
+                        self.state = States::AfterDocTypePublicIdentifier;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::AbruptDoctypePublicIdentifier);
+                        self.builder.commit_buffer_to_public_id();
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => self.builder.push_to_buffer(char),
+                }
+            },
+
+            States::DocTypePublicIdentifierSingleQuoted => {
+                match char {
+                    '\'' => {
+                        self.builder.commit_buffer_to_public_id();
+                        self.state = States::AfterDocTypePublicIdentifier;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::AbruptDoctypePublicIdentifier);
+                        self.builder.commit_buffer_to_public_id();
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => self.builder.push_to_buffer(char),
+                }
+            },
+
+            States::AfterDocTypePublicIdentifier => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => self.state = States::BetweenDocTypePublicSystemIdentifiers,
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    '"' => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers);
+                        self.state = States::DocTypeSystemIdentifierDoubleQuoted;
+                    },
+                    '\'' => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers);
+                        self.state = States::DocTypeSystemIdentifierSingleQuoted;
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::BetweenDocTypePublicSystemIdentifiers => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { /* ignore */ },
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    '"' => self.state = States::DocTypeSystemIdentifierDoubleQuoted,
+                    '\'' => self.state = States::DocTypeSystemIdentifierSingleQuoted,
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::AfterDocTypeSystemKeyword => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => self.state = States::BeforeDocTypeSystemIdentifier,
+                    '"' => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword);
+                        self.state = States::DocTypeSystemIdentifierDoubleQuoted;
+                    },
+                    '\'' => {
+                        self.parse_error(ParseErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword);
+                        self.state = States::DocTypeSystemIdentifierSingleQuoted;
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingDoctypeSystemIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::BeforeDocTypeSystemIdentifier => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { /* ignore */ },
+                    '"' => {
+                        self.state = States::DocTypeSystemIdentifierDoubleQuoted;
+                    },
+                    '\'' => {
+                        self.state = States::DocTypeSystemIdentifierSingleQuoted;
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::MissingDoctypeSystemIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier);
+                        self.builder.force_quirks();
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::DocTypeSystemIdentifierDoubleQuoted => {
+                match char {
+                    '"' => {
+                        self.builder.commit_buffer_to_system_id();
+                        self.state = States::AfterDocTypeSystemIdentifier;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::AbruptDoctypeSystemIdentifier);
+                        self.builder.commit_buffer_to_system_id();
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => self.builder.push_to_buffer(char),
+                }
+            },
+
+            States::DocTypeSystemIdentifierSingleQuoted => {
+                match char {
+                    '\'' => {
+                        self.builder.commit_buffer_to_system_id();
+                        self.state = States::AfterDocTypeSystemIdentifier;
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.builder.push_replacement_character_to_buffer();
+                    },
+                    '>' => {
+                        self.parse_error(ParseErrorKind::AbruptDoctypeSystemIdentifier);
+                        self.builder.commit_buffer_to_system_id();
+                        self.builder.force_quirks();
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => self.builder.push_to_buffer(char),
+                }
+            },
+
+            States::AfterDocTypeSystemIdentifier => {
+                match char {
+                    '\t' |
+                    '\n'/* LF */ |
+                    '\u{000C}' /* FF */ |
+                    ' ' => { /* ignore */ },
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier);
+                        self.state = States::BogusDocType;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#bogus-doctype-state
+            States::BogusDocType => {
+                match char {
+                    '>' => {
+                        self.state = States::Data;
+                        return Ok(Some(self.builder.build()));
+                    },
+                    '\0' => {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                    },
+                    _ => { /* ignore */ }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#cdata-section-state
+            States::CDataSection => {
+                match char {
+                    ']' => self.state = States::CDataSectionBracket,
+                    _ => self.emit_character(char),
+                }
+            },
+
+            States::CDataSectionBracket => {
+                match char {
+                    ']' => self.state = States::CDataSectionEnd,
+                    _ => {
+                        self.emit_str("]");
+                        self.state = States::CDataSection;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::CDataSectionEnd => {
+                match char {
+                    ']' => self.emit_character(char),
+                    '>' => self.state = States::Data,
+                    _ => {
+                        self.emit_str("]]");
+                        self.state = States::CDataSection;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#character-reference-state
+            States::CharacterReference => {
+                self.temporary_buffer.clear();
+                self.temporary_buffer.push('&');
+                match char {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' => {
+                        self.state = States::NamedCharacterReference;
+                        self.stream.reconsume();
+                    },
+                    '#' => {
+                        self.temporary_buffer.push(char);
+                        self.state = States::NumericCharacterReference;
+                    },
+                    _ => {
+                        self.flush_temporary_buffer();
+                        self.state = self.return_state;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#named-character-reference-state
+            States::NamedCharacterReference => {
+                self.stream.reconsume();
+                if let Some((len, value)) = self.match_named_character_reference() {
+                    let matched = self.stream.peek_slice(len).to_string();
+                    self.stream.advance_by(len);
+                    let ends_with_semicolon = matched.ends_with(';');
+                    let historical = self.charref_in_attribute()
+                        && !ends_with_semicolon
+                        && matches!(self.stream.peek(0),
+                            Some('=' | 'a'..='z' | 'A'..='Z' | '0'..='9'));
+                    if historical {
+                        self.temporary_buffer.push_str(&matched);
+                    } else {
+                        // reported where the semicolon should have been
+                        if !ends_with_semicolon {
+                            self.parse_error_at(
+                                ParseErrorKind::MissingSemicolonAfterCharacterReference,
+                                self.stream.position()
+                            );
+                        }
+                        self.temporary_buffer.clear();
+                        self.temporary_buffer.push_str(value);
+                    }
+                    self.flush_temporary_buffer();
+                    self.state = self.return_state;
+                } else {
+                    self.flush_temporary_buffer();
+                    self.state = States::AmbiguousAmpersand;
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#ambiguous-ampersand-state
+            States::AmbiguousAmpersand => {
+                match char {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' => {
+                        if self.charref_in_attribute() {
+                            self.builder.push_to_buffer(char);
+                        } else {
+                            self.emit_character(char);
+                        }
+                    },
+                    ';' => {
+                        self.parse_error(ParseErrorKind::UnknownNamedCharacterReference);
+                        self.state = self.return_state;
+                        self.stream.reconsume();
+                    },
+                    _ => {
+                        self.state = self.return_state;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-state
+            States::NumericCharacterReference => {
+                self.character_reference_code = 0;
+                match char {
+                    'x' | 'X' => {
+                        self.temporary_buffer.push(char);
+                        self.state = States::HexadecimalCharacterReferenceStart;
+                    },
+                    _ => {
+                        self.state = States::DecimalCharacterReferenceStart;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::HexadecimalCharacterReferenceStart => {
+                match char {
+                    '0'..='9' | 'a'..='f' | 'A'..='F' => {
+                        self.state = States::HexadecimalCharacterReference;
+                        self.stream.reconsume();
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                        self.flush_temporary_buffer();
+                        self.state = self.return_state;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::DecimalCharacterReferenceStart => {
+                match char {
+                    '0'..='9' => {
+                        self.state = States::DecimalCharacterReference;
+                        self.stream.reconsume();
+                    },
+                    _ => {
+                        self.parse_error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                        self.flush_temporary_buffer();
+                        self.state = self.return_state;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::HexadecimalCharacterReference => {
+                match char {
+                    '0'..='9' | 'a'..='f' | 'A'..='F' => {
+                        let digit = char.to_digit(16).unwrap();
+                        self.character_reference_code = self.character_reference_code
+                            .saturating_mul(16)
+                            .saturating_add(digit);
+                    },
+                    ';' => self.state = States::NumericCharacterReferenceEnd,
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                        self.state = States::NumericCharacterReferenceEnd;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            States::DecimalCharacterReference => {
+                match char {
+                    '0'..='9' => {
+                        let digit = char.to_digit(10).unwrap();
+                        self.character_reference_code = self.character_reference_code
+                            .saturating_mul(10)
+                            .saturating_add(digit);
+                    },
+                    ';' => self.state = States::NumericCharacterReferenceEnd,
+                    _ => {
+                        self.parse_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                        self.state = States::NumericCharacterReferenceEnd;
+                        self.stream.reconsume();
+                    }
+                }
+            },
+
+            //https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+            States::NumericCharacterReferenceEnd => {
+                // this state does not consume anything
+                self.stream.reconsume();
+                self.numeric_character_reference_end();
+            },
         }
         Ok(None)
     }
 }
+
+//https://html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+// without a parser to switch states, the tokenizer does what tree
+// construction would for the elements whose contents aren't markup
+fn text_state_for(tag_name: &str) -> Option<States> {
+    match tag_name {
+        "title" | "textarea" => Some(States::RCData),
+        "style" | "xmp" | "iframe" | "noembed" | "noframes" => Some(States::RawText),
+        "script" => Some(States::ScriptData),
+        "plaintext" => Some(States::PlainText),
+        _ => None,
+    }
+}
+
+impl Tokenizer {
+    // the next token and its span when used on its own, stopping at the end
+    // of the file or of the input so far
+    fn next_standalone(&mut self) -> Option<(Token, Span)> {
+        loop {
+            if self.done || self.needs_input() {
+                return None;
+            }
+            // an error here means the token builder was left in a bad
+            // state, tokenizing can't go on from there
+            match self.get_next_token().ok()? {
+                Some((Token::EndOfFile, _)) => return None,
+                Some((token, span)) => {
+                    if let Token::StartTag(ref tag) = token {
+                        if let Some(state) = text_state_for(&tag.name) {
+                            self.state = state;
+                        }
+                    }
+                    return Some((token, span));
+                },
+                None => {},
+            }
+        }
+    }
+}
+
+// tokens are owned, so they can be kept around after the tokenizer and its
+// input are gone. Parse errors are still collected, see take_errors.
+//
+// None is returned both at the end of the file and when the input pushed so
+// far has run out before finish_input is called. In the latter case pushing
+// more input lets iteration carry on, is_finished tells the two apart.
+impl Iterator for Tokenizer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.next_standalone().map(|(token, _)| token)
+    }
+}
+
+pub struct SpannedTokens {
+    tokenizer: Tokenizer,
+}
+
+impl SpannedTokens {
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        self.tokenizer.take_errors()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tokenizer.is_finished()
+    }
+}
+
+impl Iterator for SpannedTokens {
+    type Item = (Token, Span);
+
+    fn next(&mut self) -> Option<(Token, Span)> {
+        self.tokenizer.next_standalone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the character data and parse error codes from tokenizing input,
+    // with CDATA sections recognised if foreign is set
+    fn tokenize(input: &str, foreign: bool) -> (String, Vec<&'static str>) {
+        let mut tokenizer = Tokenizer::with_input(input);
+        tokenizer.foreign_content = foreign;
+        let mut text = String::new();
+        for token in tokenizer.by_ref() {
+            if let Token::Characters(data) = token {
+                text.push_str(&data);
+            }
+        }
+        let errors = tokenizer.take_errors()
+            .iter()
+            .map(|error| error.kind.code())
+            .collect();
+        (text, errors)
+    }
+
+    fn presented(tokens: &mut Tokenizer) -> Vec<String> {
+        tokens.map(|token| token.present()).collect()
+    }
+
+    // the tokens from input along with its parse errors, both as they are
+    // presented
+    fn lex(input: &str) -> (Vec<String>, Vec<String>) {
+        let mut tokenizer = Tokenizer::with_input(input);
+        let tokens = presented(&mut tokenizer);
+        let errors = tokenizer.take_errors()
+            .iter()
+            .map(|error| error.present())
+            .collect();
+        (tokens, errors)
+    }
+
+    #[test]
+    fn lexes_whole_input() {
+        let mut tokenizer = Tokenizer::with_input(
+            "<!DOCTYPE html><p class=a>x &amp; y<!--c--></p>");
+        assert_eq!(presented(&mut tokenizer), vec![
+            "DocType html",
+            "StartTag: p[ class: a]",
+            "Characters: x & y",
+            "Comment: c",
+            "EndTag: p[]",
+        ]);
+        assert!(tokenizer.is_finished());
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn switches_to_text_states() {
+        let mut tokenizer = Tokenizer::with_input("<title><p></title><script><!--</script>");
+        assert_eq!(presented(&mut tokenizer), vec![
+            "StartTag: title[]",
+            "Characters: <p>",
+            "EndTag: title[]",
+            "StartTag: script[]",
+            "Characters: <!--",
+            "EndTag: script[]",
+        ]);
+    }
+
+    #[test]
+    fn waits_for_more_input() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.push_input("<p>");
+        assert_eq!(tokenizer.next(), None);
+        assert!(!tokenizer.is_finished());
+        tokenizer.push_input("abc</p>");
+        tokenizer.finish_input();
+        assert_eq!(presented(&mut tokenizer), vec![
+            "StartTag: p[]",
+            "Characters: abc",
+            "EndTag: p[]",
+        ]);
+        assert!(tokenizer.is_finished());
+    }
+
+    #[test]
+    fn spans_cover_the_source() {
+        let input = "<p>ab</p>";
+        let mut tokens = Tokenizer::with_input(input).spanned();
+        let sources: Vec<&str> = tokens
+            .by_ref()
+            .map(|(_, span)| &input[span.start.offset..span.end.offset])
+            .collect();
+        assert_eq!(sources, vec!["<p>", "ab", "</p>"]);
+        assert!(tokens.is_finished());
+        assert!(tokens.take_errors().is_empty());
+    }
+
+    #[test]
+    fn eof_in_cdata_section() {
+        assert_eq!(
+            tokenize("<![CDATA[ab", true),
+            (String::from("ab"), vec!["eof-in-cdata"]));
+    }
+
+    #[test]
+    fn eof_in_cdata_section_bracket() {
+        assert_eq!(
+            tokenize("<![CDATA[ab]", true),
+            (String::from("ab]"), vec!["eof-in-cdata"]));
+    }
+
+    #[test]
+    fn eof_in_cdata_section_end() {
+        assert_eq!(
+            tokenize("<![CDATA[ab]]", true),
+            (String::from("ab]]"), vec!["eof-in-cdata"]));
+    }
+
+    #[test]
+    fn eof_in_script_data_escaped_less_than_sign() {
+        assert_eq!(
+            tokenize("<script><!--<", false),
+            (String::from("<!--<"), vec!["eof-in-script-html-comment-like-text"]));
+    }
+
+    #[test]
+    fn eof_in_script_data_escaped_end_tag_open() {
+        assert_eq!(
+            tokenize("<script><!--</", false),
+            (String::from("<!--</"), vec!["eof-in-script-html-comment-like-text"]));
+    }
+
+    #[test]
+    fn eof_in_script_data_escaped_end_tag_name() {
+        assert_eq!(
+            tokenize("<script><!--</scr", false),
+            (String::from("<!--</scr"), vec!["eof-in-script-html-comment-like-text"]));
+    }
+
+    #[test]
+    fn eof_in_script_data_end_tag_name() {
+        assert_eq!(
+            tokenize("<script>x</scr", false),
+            (String::from("x</scr"), vec![]));
+    }
+
+    #[test]
+    fn named_character_reference() {
+        assert_eq!(lex("a&amp;b&NotEqualTilde;"), (
+            vec![String::from("Characters: a&b\u{2242}\u{338}")],
+            vec![]));
+    }
+
+    #[test]
+    fn legacy_named_character_reference_without_semicolon() {
+        assert_eq!(lex("&notit; &copy"), (
+            vec![String::from("Characters: \u{AC}it; \u{A9}")],
+            vec![
+                String::from("1:5 missing-semicolon-after-character-reference"),
+                String::from("1:14 missing-semicolon-after-character-reference"),
+            ]));
+    }
+
+    #[test]
+    fn named_character_reference_in_attribute_value() {
+        assert_eq!(lex("<a href='?a=1&copy=2&copy;&not'>"), (
+            vec![String::from("StartTag: a[ href: ?a=1&copy=2\u{A9}\u{AC}]")],
+            vec![String::from("1:31 missing-semicolon-after-character-reference")]));
+    }
+
+    #[test]
+    fn unknown_named_character_reference() {
+        assert_eq!(lex("&bogus; &bogus"), (
+            vec![String::from("Characters: &bogus; &bogus")],
+            vec![String::from("1:7 unknown-named-character-reference")]));
+    }
+
+    #[test]
+    fn characters_flushed_at_eof_are_coalesced() {
+        assert_eq!(lex("a&amp;b&lt"), (
+            vec![String::from("Characters: a&b<")],
+            vec![String::from("1:11 missing-semicolon-after-character-reference")]));
+    }
+
+    #[test]
+    fn numeric_character_reference() {
+        assert_eq!(lex("&#65;&#x62;&#X63;"), (vec![String::from("Characters: Abc")], vec![]));
+    }
+
+    #[test]
+    fn numeric_character_reference_replacements() {
+        assert_eq!(lex("&#128;&#0;&#xD800;&#x110000;&#x9F;"), (
+            vec![String::from("Characters: \u{20AC}\u{FFFD}\u{FFFD}\u{FFFD}\u{178}")],
+            vec![
+                String::from("1:7 control-character-reference"),
+                String::from("1:11 null-character-reference"),
+                String::from("1:19 surrogate-character-reference"),
+                String::from("1:29 character-reference-outside-unicode-range"),
+                String::from("1:35 control-character-reference"),
+            ]));
+    }
+
+    #[test]
+    fn malformed_numeric_character_reference() {
+        assert_eq!(lex("&#;&#x;&#65 &#xFDD0;"), (
+            vec![String::from("Characters: &#;&#x;A \u{FDD0}")],
+            vec![
+                String::from("1:3 absence-of-digits-in-numeric-character-reference"),
+                String::from("1:7 absence-of-digits-in-numeric-character-reference"),
+                String::from("1:12 missing-semicolon-after-character-reference"),
+                String::from("1:21 noncharacter-character-reference"),
+            ]));
+    }
+
+    #[test]
+    fn script_data_escaped() {
+        assert_eq!(lex("<script><!--a</b>-->c</script>"), (
+            vec![
+                String::from("StartTag: script[]"),
+                String::from("Characters: <!--a</b>-->c"),
+                String::from("EndTag: script[]"),
+            ],
+            vec![]));
+        assert_eq!(lex("<script><!--a</SCRIPT>b"), (
+            vec![
+                String::from("StartTag: script[]"),
+                String::from("Characters: <!--a"),
+                String::from("EndTag: script[]"),
+                String::from("Characters: b"),
+            ],
+            vec![]));
+    }
+
+    #[test]
+    fn script_data_double_escaped() {
+        assert_eq!(lex("<script><!--<script>a</script>b-->c</script>"), (
+            vec![
+                String::from("StartTag: script[]"),
+                String::from("Characters: <!--<script>a</script>b-->c"),
+                String::from("EndTag: script[]"),
+            ],
+            vec![]));
+        // inside the double escape a script end tag is just text
+        assert_eq!(lex("<script><!--<script></script></script>"), (
+            vec![
+                String::from("StartTag: script[]"),
+                String::from("Characters: <!--<script></script>"),
+                String::from("EndTag: script[]"),
+            ],
+            vec![]));
+    }
+
+    #[test]
+    fn null_in_script_data_escaped() {
+        assert_eq!(lex("<script><!--\0<script>\0</script>"), (
+            vec![
+                String::from("StartTag: script[]"),
+                String::from("Characters: <!--\u{FFFD}<script>\u{FFFD}</script>"),
+            ],
+            vec![
+                String::from("1:13 unexpected-null-character"),
+                String::from("1:22 unexpected-null-character"),
+                String::from("1:32 eof-in-script-html-comment-like-text"),
+            ]));
+    }
+
+    #[test]
+    fn rawtext_end_tag() {
+        assert_eq!(lex("<style>a&amp;</b></stylex></STYLE>"), (
+            vec![
+                String::from("StartTag: style[]"),
+                String::from("Characters: a&amp;</b></stylex>"),
+                String::from("EndTag: style[]"),
+            ],
+            vec![]));
+        assert_eq!(lex("<xmp>a</xmp x=1 />"), (
+            vec![
+                String::from("StartTag: xmp[]"),
+                String::from("Characters: a"),
+                String::from("EndTag: xmp[ x: 1]"),
+            ],
+            vec![
+                String::from("1:7 end-tag-with-attributes"),
+                String::from("1:7 end-tag-with-trailing-solidus"),
+            ]));
+    }
+
+    #[test]
+    fn plaintext_has_no_end_tag() {
+        assert_eq!(lex("<plaintext>a</plaintext>&amp;"), (
+            vec![
+                String::from("StartTag: plaintext[]"),
+                String::from("Characters: a</plaintext>&amp;"),
+            ],
+            vec![]));
+    }
+
+    #[test]
+    fn comment_closed_with_bang() {
+        assert_eq!(lex("<!--a--!>b"), (
+            vec![String::from("Comment: a"), String::from("Characters: b")],
+            vec![String::from("1:9 incorrectly-closed-comment")]));
+        assert_eq!(lex("<!--a--!b-->"), (
+            vec![String::from("Comment: a--!b")],
+            vec![]));
+    }
+
+    #[test]
+    fn nested_comment() {
+        assert_eq!(lex("<!--a<!--b-->"), (
+            vec![String::from("Comment: a<!--b")],
+            vec![String::from("1:10 nested-comment")]));
+        assert_eq!(lex("<!--a<!-->"), (
+            vec![String::from("Comment: a<!")],
+            vec![]));
+    }
+
+    #[test]
+    fn abruptly_closed_comment() {
+        assert_eq!(lex("<!--><!--->"), (
+            vec![String::from("Comment: "), String::from("Comment: ")],
+            vec![
+                String::from("1:5 abrupt-closing-of-empty-comment"),
+                String::from("1:11 abrupt-closing-of-empty-comment"),
+            ]));
+    }
+
+    #[test]
+    fn bogus_comment() {
+        assert_eq!(lex("<!x><?y>"), (
+            vec![String::from("Comment: x"), String::from("Comment: ?y")],
+            vec![
+                String::from("1:3 incorrectly-opened-comment"),
+                String::from("1:6 unexpected-question-mark-instead-of-tag-name"),
+            ]));
+    }
+
+    #[test]
+    fn doctype_identifiers() {
+        assert_eq!(
+            lex("<!DOCTYPE HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" 'http://x'>\
+                <!doctype html system 'about:legacy-compat'>"),
+            (vec![
+                String::from("DocType html PUBLIC: -//W3C//DTD HTML 4.01//EN SYSTEM: http://x"),
+                String::from("DocType html SYSTEM: about:legacy-compat"),
+            ], vec![]));
+    }
+
+    #[test]
+    fn malformed_doctype_forces_quirks() {
+        assert_eq!(lex("<!DOCTYPE html PUBLIC\"x\"><!DOCTYPE html bogus><!DOCTYPE>"), (
+            vec![
+                String::from("DocType html PUBLIC: x"),
+                String::from("DocType html (force-quirks)"),
+                String::from("DocType  (force-quirks)"),
+            ],
+            vec![
+                String::from("1:22 missing-whitespace-after-doctype-public-keyword"),
+                String::from("1:41 invalid-character-sequence-after-doctype-name"),
+                String::from("1:56 missing-doctype-name"),
+            ]));
+    }
+
+    #[test]
+    fn cdata_section_in_foreign_content() {
+        assert_eq!(
+            tokenize("<![CDATA[a<b>&amp;]]]>c]]>", true),
+            (String::from("a<b>&amp;]c]]>"), vec![]));
+    }
+
+    #[test]
+    fn cdata_section_in_html_content() {
+        assert_eq!(lex("<![CDATA[x]]>"), (
+            vec![String::from("Comment: [CDATA[x]]")],
+            vec![String::from("1:3 cdata-in-html-content")]));
+    }
+
+    // what input cut off at each point comes out as, with the error for
+    // running out in that state
+    fn truncated(cases: &[(&str, &[&str], &str)]) {
+        for &(input, tokens, error) in cases {
+            let tokens = tokens.iter().map(|token| token.to_string()).collect();
+            let errors = vec![error.to_string()];
+            assert_eq!(lex(input), (tokens, errors), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn eof_in_tag_states() {
+        truncated(&[
+            ("<", &["Characters: <"], "1:2 eof-before-tag-name"),
+            ("</", &["Characters: </"], "1:3 eof-before-tag-name"),
+            ("<di", &[], "1:4 eof-in-tag"),
+            ("</di", &[], "1:5 eof-in-tag"),
+            ("<div/", &[], "1:6 eof-in-tag"),
+        ]);
+    }
+
+    #[test]
+    fn eof_in_attribute_states() {
+        truncated(&[
+            ("<div a", &[], "1:7 eof-in-tag"),
+            ("<div a ", &[], "1:8 eof-in-tag"),
+            ("<div a=", &[], "1:8 eof-in-tag"),
+            ("<div a=\"x", &[], "1:10 eof-in-tag"),
+            ("<div a='x", &[], "1:10 eof-in-tag"),
+            ("<div a=x", &[], "1:9 eof-in-tag"),
+            ("<div a=\"x\"", &[], "1:11 eof-in-tag"),
+        ]);
+    }
+
+    #[test]
+    fn eof_in_comment_states() {
+        truncated(&[
+            ("<!", &["Comment: "], "1:3 incorrectly-opened-comment"),
+            ("<!-", &["Comment: -"], "1:3 incorrectly-opened-comment"),
+            ("<!--", &["Comment: "], "1:5 eof-in-comment"),
+            ("<!---", &["Comment: "], "1:6 eof-in-comment"),
+            ("<!--a", &["Comment: a"], "1:6 eof-in-comment"),
+            ("<!--a-", &["Comment: a"], "1:7 eof-in-comment"),
+            ("<!--a--", &["Comment: a"], "1:8 eof-in-comment"),
+            ("<!--a--!", &["Comment: a"], "1:9 eof-in-comment"),
+            ("<!--a<", &["Comment: a<"], "1:7 eof-in-comment"),
+            ("<!--a<!", &["Comment: a<!"], "1:8 eof-in-comment"),
+            ("<!--a<!-", &["Comment: a<!"], "1:9 eof-in-comment"),
+        ]);
+    }
+
+    #[test]
+    fn eof_in_doctype_states() {
+        truncated(&[
+            ("<!DOCTYPE", &["DocType  (force-quirks)"], "1:10 eof-in-doctype"),
+            ("<!DOCTYPE ", &["DocType  (force-quirks)"], "1:11 eof-in-doctype"),
+            ("<!DOCTYPE ht", &["DocType ht (force-quirks)"], "1:13 eof-in-doctype"),
+            ("<!DOCTYPE html ", &["DocType html (force-quirks)"], "1:16 eof-in-doctype"),
+            ("<!DOCTYPE html PUBLIC", &["DocType html (force-quirks)"], "1:22 eof-in-doctype"),
+            ("<!DOCTYPE html PUBLIC ", &["DocType html (force-quirks)"], "1:23 eof-in-doctype"),
+            (
+                "<!DOCTYPE html PUBLIC \"x",
+                &["DocType html PUBLIC: x (force-quirks)"],
+                "1:25 eof-in-doctype",
+            ),
+            (
+                "<!DOCTYPE html PUBLIC \"x\" ",
+                &["DocType html PUBLIC: x (force-quirks)"],
+                "1:27 eof-in-doctype",
+            ),
+            (
+                "<!DOCTYPE html PUBLIC 'x' 'y",
+                &["DocType html PUBLIC: x SYSTEM: y (force-quirks)"],
+                "1:29 eof-in-doctype",
+            ),
+            ("<!DOCTYPE html SYSTEM ", &["DocType html (force-quirks)"], "1:23 eof-in-doctype"),
+            (
+                "<!DOCTYPE html SYSTEM \"y",
+                &["DocType html SYSTEM: y (force-quirks)"],
+                "1:25 eof-in-doctype",
+            ),
+            (
+                "<!DOCTYPE html SYSTEM \"y\"",
+                &["DocType html SYSTEM: y (force-quirks)"],
+                "1:26 eof-in-doctype",
+            ),
+        ]);
+        // the bogus DOCTYPE state just emits what it has
+        assert_eq!(lex("<!DOCTYPE html bogus"), (
+            vec![String::from("DocType html (force-quirks)")],
+            vec![String::from("1:16 invalid-character-sequence-after-doctype-name")]));
+    }
+
+    #[test]
+    fn eof_in_character_reference_states() {
+        assert_eq!(lex("&"), (vec![String::from("Characters: &")], vec![]));
+        assert_eq!(lex("&am"), (vec![String::from("Characters: &am")], vec![]));
+        truncated(&[
+            ("&amp", &["Characters: &"], "1:5 missing-semicolon-after-character-reference"),
+            ("a&notit", &["Characters: a\u{AC}it"], "1:6 missing-semicolon-after-character-reference"),
+            ("&#", &["Characters: &#"], "1:3 absence-of-digits-in-numeric-character-reference"),
+            ("&#x", &["Characters: &#x"], "1:4 absence-of-digits-in-numeric-character-reference"),
+            ("&#65", &["Characters: A"], "1:5 missing-semicolon-after-character-reference"),
+            ("&#x41", &["Characters: A"], "1:6 missing-semicolon-after-character-reference"),
+        ]);
+    }
+
+    #[test]
+    fn multi_byte_characters() {
+        assert_eq!(lex("<p title=\u{E9}>\u{FC}\u{20AC}\u{1F600}&amp;</p>"), (
+            vec![
+                String::from("StartTag: p[ title: \u{E9}]"),
+                String::from("Characters: \u{FC}\u{20AC}\u{1F600}&"),
+                String::from("EndTag: p[]"),
+            ],
+            vec![]));
+    }
+
+    #[test]
+    fn only_ascii_is_lowercased() {
+        assert_eq!(lex("<DIV \u{C4}B=1 Cl\u{C4}ss=X>"), (
+            vec![String::from("StartTag: div[ \u{C4}b: 1 cl\u{C4}ss: X]")],
+            vec![]));
+        assert_eq!(lex("<\u{C4}>"), (
+            vec![String::from("Characters: <\u{C4}>")],
+            vec![String::from("1:2 invalid-first-character-of-tag-name")]));
+    }
+
+    #[test]
+    fn null_characters_are_replaced() {
+        assert_eq!(lex("<a\0 b\0=c\0 d='\0' e=\"\0\">\0"), (
+            vec![
+                String::from("StartTag: a\u{FFFD}[ b\u{FFFD}: c\u{FFFD} d: \u{FFFD} e: \u{FFFD}]"),
+                // the tree builder deals with NULs in text
+                String::from("Characters: \0"),
+            ],
+            vec![
+                String::from("1:3 unexpected-null-character"),
+                String::from("1:6 unexpected-null-character"),
+                String::from("1:9 unexpected-null-character"),
+                String::from("1:14 unexpected-null-character"),
+                String::from("1:20 unexpected-null-character"),
+                String::from("1:23 unexpected-null-character"),
+            ]));
+        assert_eq!(lex("<!--\0--><!DOCTYPE h\0>"), (
+            vec![String::from("Comment: \u{FFFD}"), String::from("DocType h\u{FFFD}")],
+            vec![
+                String::from("1:5 unexpected-null-character"),
+                String::from("1:20 unexpected-null-character"),
+            ]));
+    }
+
+    #[test]
+    fn duplicate_attribute() {
+        assert_eq!(lex("<p a=1 a=2 b>\n<p A b B=x>"), (
+            vec![
+                String::from("StartTag: p[ a: 1 b: ]"),
+                String::from("Characters: \n"),
+                String::from("StartTag: p[ a:  b: ]"),
+            ],
+            vec![
+                String::from("1:8 duplicate-attribute"),
+                String::from("2:8 duplicate-attribute"),
+            ]));
+    }
+
+    // the tokens from input with the byte offsets their spans cover
+    fn spanned(input: &str) -> Vec<(String, usize, usize)> {
+        Tokenizer::with_input(input)
+            .spanned()
+            .map(|(token, span)| (token.present(), span.start.offset, span.end.offset))
+            .collect()
+    }
+
+    #[test]
+    fn characters_are_coalesced_into_runs() {
+        // references and newlines don't split a run, and its span covers
+        // all of the source it came from
+        assert_eq!(spanned("a&amp;b\r\nc&#x41;d e"), vec![
+            (String::from("Characters: a&b\ncAd e"), 0, 19),
+        ]);
+        assert_eq!(spanned("<title>a<b</c>&lt;</title>x"), vec![
+            (String::from("StartTag: title[]"), 0, 7),
+            (String::from("Characters: a<b</c><"), 7, 18),
+            (String::from("EndTag: title[]"), 18, 26),
+            (String::from("Characters: x"), 26, 27),
+        ]);
+    }
+
+    #[test]
+    fn runs_end_at_other_tokens() {
+        assert_eq!(spanned("a<!--c-->b<br>c"), vec![
+            (String::from("Characters: a"), 0, 1),
+            (String::from("Comment: c"), 1, 9),
+            (String::from("Characters: b"), 9, 10),
+            (String::from("StartTag: br[]"), 10, 14),
+            (String::from("Characters: c"), 14, 15),
+        ]);
+    }
+}
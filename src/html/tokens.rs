@@ -1,5 +1,5 @@
 use std::mem;
-use crate::html::HTMLError;
+use crate::html::{dom::Namespace, HTMLError};
 
 #[derive(Debug)]
 pub enum TokenVariant {
@@ -7,18 +7,21 @@ pub enum TokenVariant {
     StartTag,
     EndTag,
     Comment,
-    Character,
 }
 
 #[derive(Default)]
-pub struct TokenBuilder<'stream> {
+pub struct TokenBuilder {
     variant: Option<TokenVariant>,
-    pub doctype: DocType<'stream>,
-    pub tag: Tag<'stream>,
-    pub buffer: Vec<&'stream u8>,
+    pub doctype: DocType,
+    pub tag: Tag,
+    pub buffer: String,
+    last_start_tag: String,
+    // set when the attribute being built repeats an earlier name, its value
+    // is still tokenized but goes nowhere
+    dropping_attribute: bool,
 }
 
-impl<'stream> TokenBuilder<'stream> {
+impl TokenBuilder {
     pub fn set_variant(&mut self, variant: TokenVariant) -> Result<(), HTMLError> {
         match self.variant {
             Some(_) => Err(HTMLError::TokenBuilderImproperlyCleared),
@@ -29,38 +32,64 @@ impl<'stream> TokenBuilder<'stream> {
         }
     }
 
-    pub fn push_to_buffer(&mut self, char: &'stream u8) {
+    pub fn push_to_buffer(&mut self, char: char) {
         self.buffer.push(char);
     }
 
     pub fn push_replacement_character_to_buffer(&mut self) {
-        self.buffer.push(&0xFF);
-        self.buffer.push(&0xFD);
+        self.buffer.push('\u{FFFD}');
     }
 
     pub fn commit_buffer_to_doctype_name(&mut self) {
         self.doctype.name = mem::take(&mut self.buffer);
     }
 
-    pub fn commit_buffer_to_attr_keys(&mut self) {
-        self.tag.attr_keys.push(mem::take(&mut self.buffer));
+    pub fn commit_buffer_to_public_id(&mut self) {
+        self.doctype.public_id = Some(mem::take(&mut self.buffer));
+    }
+
+    pub fn commit_buffer_to_system_id(&mut self) {
+        self.doctype.system_id = Some(mem::take(&mut self.buffer));
+    }
+
+    // starts a new attribute named by the buffer, its value stays empty
+    // unless one follows. Returns false if the tag already has an attribute
+    // by that name, in which case the new one is dropped.
+    pub fn commit_buffer_to_attr_name(&mut self) -> bool {
+        let name = mem::take(&mut self.buffer);
+        self.dropping_attribute = self.tag.has_attribute(&name);
+        if !self.dropping_attribute {
+            self.tag.attributes.push(Attribute {
+                name,
+                value: String::new(),
+                namespace: None,
+            });
+        }
+        !self.dropping_attribute
     }
 
     pub fn commit_buffer_to_attr_value(&mut self) {
-        self.tag.attr_values.push(mem::take(&mut self.buffer));
+        let value = mem::take(&mut self.buffer);
+        if self.dropping_attribute {
+            return;
+        }
+        if let Some(attribute) = self.tag.attributes.last_mut() {
+            attribute.value = value;
+        }
     }
 
     pub fn force_quirks(&mut self) {
         self.doctype.force_quirks = true;
     }
 
-    pub fn build(&mut self) -> Token<'stream> {
+    pub fn build(&mut self) -> Token {
         // TODO: we need to properly handle error here! lol 
         let token = match self.variant.as_mut().unwrap() {
             TokenVariant::Doctype => {
                 Token::Doctype(mem::take(&mut self.doctype))
             },
             TokenVariant::StartTag => {
+                self.last_start_tag = self.tag.name.clone();
                 Token::StartTag(mem::take(&mut self.tag))
             },
             TokenVariant::EndTag => {
@@ -69,59 +98,65 @@ impl<'stream> TokenBuilder<'stream> {
             TokenVariant::Comment => {
                 Token::Comment(mem::take(&mut self.buffer))
             },
-            TokenVariant::Character => {
-                Token::Character(self.buffer.pop().unwrap())
-            },
         };
+        self.clear();
+        token
+    }
+
+    pub fn clear(&mut self) {
         self.variant = None;
         self.doctype = DocType::default();
         self.tag = Tag::default();
-        self.buffer = Vec::new();
-        token
+        self.buffer = String::new();
+        self.dropping_attribute = false;
     }
 
+    //https://html.spec.whatwg.org/multipage/parsing.html#appropriate-end-tag-token
     pub fn check_tag_validitiy(&self) -> bool {
-        // TODO: compare buffer to self.last_tag
-        true
+        self.tag.name == self.last_start_tag
     }
 }
 
 
-#[derive(Debug)]
-pub enum Token<'stream> {
-    Doctype(DocType<'stream>),
-    StartTag(Tag<'stream>),
-    EndTag(Tag<'stream>),
-    Comment(Vec<&'stream u8>),
-    Character(&'stream u8),
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Doctype(DocType),
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    Characters(String),
     EndOfFile,
 }
 
-impl<'stream> Token<'stream> {
-
-    fn printer_helper(&self, data: &[&'stream u8]) -> String {
-        String::from_utf8(data
-            .iter()
-            .map(|&x| *x)
-            .collect()).unwrap()
-    }
-
+impl Token {
     pub fn present(&self) -> String {
         match self {
             Self::EndOfFile => String::from("EOF"),
             Self::Doctype(doctype) => {
                 let mut fmt_str = String::new();
                 fmt_str.push_str("DocType ");
+                fmt_str.push_str(&doctype.name);
+                if let Some(public_id) = &doctype.public_id {
+                    fmt_str.push_str(" PUBLIC: ");
+                    fmt_str.push_str(public_id);
+                }
+                if let Some(system_id) = &doctype.system_id {
+                    fmt_str.push_str(" SYSTEM: ");
+                    fmt_str.push_str(system_id);
+                }
+                if doctype.force_quirks {
+                    fmt_str.push_str(" (force-quirks)");
+                }
                 fmt_str
             },
             Self::Comment(data) => {
                 let mut fmt_str = "Comment: ".to_string();
-                fmt_str.push_str(&self.printer_helper(data));
+                fmt_str.push_str(data);
                 fmt_str
             },
-            Self::Character(byte) => {
-                let mut fmt_str = "Character: ".to_string();
-                fmt_str.push_str(&self.printer_helper(&[*byte]));
+            Self::Characters(data) => {
+                let mut fmt_str = "Characters: ".to_string();
+                fmt_str.push_str(data);
                 fmt_str
             },
             Self::EndTag(tag) |
@@ -132,22 +167,13 @@ impl<'stream> Token<'stream> {
                     Self::StartTag(_) => fmt_str.push_str("StartTag: "),
                     _ => {}
                 }
-                fmt_str.push_str(&self.printer_helper(&tag.name));
+                fmt_str.push_str(&tag.name);
                 fmt_str.push('[');
-                for i in 0..tag.attr_keys.len() {
+                for attribute in tag.attributes.iter() {
                     fmt_str.push(' ');
-                    let key = tag.attr_keys.get(i).unwrap();
-                    let key_str = self.printer_helper(key); 
-                    fmt_str.push_str(&key_str);
+                    fmt_str.push_str(&attribute.name);
                     fmt_str.push_str(": ");
-                    match tag.attr_values.get(i) {
-                        Some(bytes) => {
-                            fmt_str.push_str(&self.printer_helper(bytes));
-                        },
-                        None => {
-                            fmt_str.push_str("EMPTY");
-                        }
-                    }
+                    fmt_str.push_str(&attribute.value);
                 }
                 fmt_str.push(']');
                 fmt_str
@@ -157,46 +183,41 @@ impl<'stream> Token<'stream> {
 }
 
 //https://html.spec.whatwg.org/multipage/parsing.html#tokenization
-#[derive(Debug, Default)]
-pub struct DocType<'stream> {
-    pub name: Vec<&'stream u8>, 
-    public_id: Vec<&'stream u8>, 
-    system_id: Vec<&'stream u8>,
-    force_quirks: bool,
-}
-
-impl<'stream> DocType<'stream> {
-    pub fn test(&mut self) {
-        self.force_quirks = true;
-    }
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DocType {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub force_quirks: bool,
 }
 
-// TODO: this needs to go 
-fn dom_string_from_token_string(token_string: &[&u8]) -> String {
-    String::from_utf8(token_string
-        .iter()
-        .map(|&x| *x)
-        .collect()).unwrap()
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: String,
+    // only set on the foreign attributes adjusted in foreign content, such
+    // as xlink:href, whose name is then just the local name
+    pub namespace: Option<Namespace>,
 }
 
-#[derive(Debug, Default)]
-pub struct Tag<'stream> {
-    pub name: Vec<&'stream u8>,
+// attributes are kept in source order, names are unique as later
+// duplicates are dropped by the tokenizer
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tag {
+    pub name: String,
     pub self_closing: bool,
-    pub attr_keys: Vec<Vec<&'stream u8>>,
-    pub attr_values: Vec<Vec<&'stream u8>>,
+    pub attributes: Vec<Attribute>,
 }
 
-impl<'stream> Tag<'stream> {
-    pub fn get_class_list(&self) -> Option<String> {
-        for (i, val) in self.attr_keys.iter().enumerate() {
-            if dom_string_from_token_string(val) == "class" {
-                return Some(
-                    dom_string_from_token_string(&self.attr_values[i])
-                    );
-            }
-        }
-        None
+impl Tag {
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| attribute.value.as_str())
     }
-}
 
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.get_attribute(name).is_some()
+    }
+}
@@ -7,8 +7,14 @@ use std::{env, fs};
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
     let data = fs::read("./simple_test.html").unwrap();
-    match html::make_dom(&data) {
-        Ok(res) => {
+    match html::make_dom(&data, None) {
+        Ok((res, errors)) => {
+            println!("{} ({:?})", res.encoding().name(), res.confidence());
+            println!("{:?}", res.quirks_mode());
+            println!("{}", res.present());
+            for error in errors {
+                println!("{}", error.present());
+            }
         },
         Err(err) => {
             println!("{:?}", err);
@@ -1,65 +1,528 @@
-pub struct Stream<'stream> {
-    pub data: &'stream Vec<u8>,
-    idx: usize,
+use crate::html::{
+    entities,
+    errors::{ParseError, ParseErrorKind},
+};
+
+// lines and columns start at 1, columns count code points rather than bytes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn start() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    // a CR, an LF or a CRLF pair each end a line
+    fn step_over(&mut self, char: char, after_cr: bool) {
+        self.offset += char.len_utf8();
+        match char {
+            '\n' if after_cr => {},
+            '\n' | '\r' => {
+                self.line += 1;
+                self.column = 1;
+            },
+            _ => self.column += 1,
+        }
+    }
+}
+
+// the range of the source a token or node came from, end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+}
+
+//https://html.spec.whatwg.org/multipage/parsing.html#preprocessing-the-input-stream
+// surrogates can't make it into a str, the decoder has already replaced them
+fn input_stream_error(char: char) -> Option<ParseErrorKind> {
+    let code = char as u32;
+    if entities::is_noncharacter(code) {
+        Some(ParseErrorKind::NoncharacterInInputStream)
+    } else if entities::is_control(code) &&
+        !matches!(char, '\t' | '\n' | '\u{000C}' | '\r' | ' ' | '\0') {
+        Some(ParseErrorKind::ControlCharacterInInputStream)
+    } else {
+        None
+    }
+}
+
+// the stream is where the input stream gets preprocessed: CR and CRLF come
+// out as a single LF, and characters the spec doesn't allow in the input are
+// reported the first time they are consumed. Input can keep arriving until
+// the stream is closed, so running out of data only means EOF after that.
+pub struct Stream {
+    data: String,
+    closed: bool,
+    position: Position,
+    // where the stream was before the last advance, so reconsuming doesn't
+    // have to work out the column again
+    previous: Position,
+    // everything before this offset has already been checked for errors,
+    // so reconsuming doesn't report a character twice
+    checked: usize,
+    errors: Vec<ParseError>,
+    //https://html.spec.whatwg.org/multipage/parsing.html#insertion-point
+    // where markup written by a script goes, as an offset into the data
+    insertion_point: Option<usize>,
 }
 
-impl<'stream> Stream<'stream> {
-    pub fn new(data: &'stream Vec<u8>) -> Self {
+impl Stream {
+    pub fn new() -> Self {
         Stream {
-            data,
-            idx: 0
+            data: String::new(),
+            closed: false,
+            position: Position::start(),
+            previous: Position::start(),
+            checked: 0,
+            errors: Vec::new(),
+            insertion_point: None,
         }
     }
-    pub fn advance(&mut self) {
-        self.idx += 1;
+
+    pub fn push_str(&mut self, chars: &str) {
+        // a CR consumed at the end of the data has already been the line
+        // break, so an LF arriving after it goes along with it
+        let split_crlf = self.position.offset == self.data.len() &&
+            self.data.ends_with('\r') &&
+            chars.starts_with('\n');
+        self.data.push_str(chars);
+        if split_crlf {
+            self.step_over(self.position.offset + 1);
+        }
     }
 
-    pub fn reconsume(&mut self) {
-        self.idx -= 1;
+    // no more input is coming
+    pub fn close(&mut self) {
+        self.closed = true;
     }
 
-    pub fn is_eof(&self) -> bool {
-        self.idx >= self.data.len()
+    pub fn is_closed(&self) -> bool {
+        self.closed
     }
 
-    pub fn current(&self) -> &'stream u8 {
-        &self.data[self.idx]
+    pub fn insertion_point(&self) -> Option<usize> {
+        self.insertion_point
     }
 
-    pub fn expect(&self, chars: &str) -> bool {
-        if chars.len() + self.idx > self.data.len() {
-            false
-        } else {
-            for (offset, byte) in chars.as_bytes().iter().enumerate() {
-                if &self.data[self.idx+offset] == byte {
-                    continue;
-                } else {
-                    return false;
+    pub fn set_insertion_point(&mut self, insertion_point: Option<usize>) {
+        self.insertion_point = insertion_point;
+    }
+
+    pub fn at_insertion_point(&self) -> bool {
+        self.insertion_point
+            .is_some_and(|insertion_point| self.position.offset >= insertion_point)
+    }
+
+    // puts chars at the insertion point and moves it to just after them,
+    // so the next write follows this one. Does nothing without an
+    // insertion point.
+    pub fn insert_str(&mut self, chars: &str) {
+        if let Some(insertion_point) = self.insertion_point {
+            let insertion_point = usize::max(insertion_point, self.position.offset);
+            self.data.insert_str(insertion_point, chars);
+            self.insertion_point = Some(insertion_point + chars.len());
+        }
+    }
+
+    // how many bytes have arrived that haven't been consumed yet
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position.offset
+    }
+
+    // where the data that can be looked at ends for now. Whatever follows
+    // the insertion point waits for the script writing there, so lookahead
+    // doesn't get to see it.
+    fn end(&self) -> usize {
+        match self.insertion_point {
+            Some(insertion_point) => usize::max(insertion_point, self.position.offset),
+            None => self.data.len(),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.data[self.position.offset..self.end()]
+    }
+
+    // moves up to end, which has to be on a code point boundary
+    fn step_over(&mut self, end: usize) {
+        let Stream { data, position, checked, errors, .. } = self;
+        let mut after_cr = data[..position.offset].ends_with('\r');
+        for char in data[position.offset..end].chars() {
+            if position.offset >= *checked {
+                if let Some(kind) = input_stream_error(char) {
+                    errors.push(ParseError::new(kind, *position));
                 }
+                *checked = position.offset + char.len_utf8();
             }
-            true
+            position.step_over(char, after_cr);
+            after_cr = char == '\r';
         }
+    }
 
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
     }
 
-    pub fn expect_insensitive(&self, chars: &str) -> bool {
-        // TODO: obviously needs to be modified for case insensitivity
-        if chars.len() + self.idx > self.data.len() {
-            false
+    pub fn advance(&mut self) {
+        self.previous = self.position;
+        let offset = self.position.offset;
+        let len = if self.data[offset..].starts_with("\r\n") {
+            2
         } else {
-            for (offset, byte) in chars.as_bytes().iter().enumerate() {
-                if &self.data[self.idx+offset] == byte {
-                    continue;
-                } else {
-                    return false;
-                }
+            self.current_raw().len_utf8()
+        };
+        self.step_over(offset + len);
+    }
+
+    // moves ahead by up to count bytes, stopping at the end of the data and
+    // never in the middle of a code point
+    pub fn advance_by(&mut self, count: usize) {
+        self.previous = self.position;
+        let offset = self.position.offset;
+        let mut end = usize::min(offset + count, self.end());
+        while !self.data.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.step_over(end);
+    }
+
+    pub fn reconsume(&mut self) {
+        // step back over however many bytes the previous code point took,
+        // a CRLF pair having been consumed as one
+        let consumed = &self.data[..self.position.offset];
+        let offset = if consumed.ends_with("\r\n") {
+            consumed.len() - 2
+        } else {
+            consumed
+                .char_indices()
+                .next_back()
+                .map(|(idx, _)| idx)
+                .unwrap_or(0)
+        };
+        if self.previous.offset == offset {
+            self.position = self.previous;
+        } else {
+            self.position = self.position_at(offset);
+        }
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.closed && self.position.offset >= self.data.len()
+    }
+
+    fn current_raw(&self) -> char {
+        self.data[self.position.offset..].chars().next().unwrap()
+    }
+
+    pub fn current(&self) -> char {
+        match self.current_raw() {
+            '\r' => '\n',
+            char => char,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.position.offset
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn position_at(&self, offset: usize) -> Position {
+        let mut position = Position::start();
+        let mut after_cr = false;
+        for char in self.data[..offset].chars() {
+            position.step_over(char, after_cr);
+            after_cr = char == '\r';
+        }
+        position
+    }
+
+    pub fn peek_slice(&self, len: usize) -> &str {
+        let idx = self.position.offset;
+        let mut end = usize::min(idx + len, self.end());
+        while !self.data.is_char_boundary(end) {
+            end -= 1;
+        }
+        &self.data[usize::min(idx, end)..end]
+    }
+
+    // consumes up to (not including) the first of the stop characters,
+    // or to the end of the data so far. Also stops short of anything that
+    // needs preprocessing, so the run comes back exactly as it is in the
+    // source.
+    pub fn consume_until(&mut self, stop: &[char]) -> String {
+        let rest = self.rest();
+        let len = rest
+            .find(|char| {
+                stop.contains(&char) ||
+                    char == '\r' ||
+                    input_stream_error(char).is_some()
+            })
+            .unwrap_or(rest.len());
+        let run = rest[..len].to_string();
+        self.previous = self.position;
+        self.step_over(self.position.offset + len);
+        run
+    }
+
+    // the nth character from the current one, after newline normalization
+    pub fn peek(&self, n: usize) -> Option<char> {
+        let mut chars = self.rest().chars().peekable();
+        let mut count = 0;
+        while let Some(char) = chars.next() {
+            let char = match char {
+                '\r' => {
+                    chars.next_if_eq(&'\n');
+                    '\n'
+                },
+                char => char,
+            };
+            if count == n {
+                return Some(char);
             }
-            true
+            count += 1;
+        }
+        None
+    }
+
+    pub fn starts_with(&self, chars: &str) -> bool {
+        self.rest().starts_with(chars)
+    }
+
+    pub fn starts_with_ci(&self, chars: &str) -> bool {
+        let rest = self.rest().as_bytes();
+        rest.len() >= chars.len() && rest[..chars.len()].eq_ignore_ascii_case(chars.as_bytes())
+    }
+
+    // consumes chars if the stream starts with them
+    pub fn consume(&mut self, chars: &str) -> bool {
+        let matched = self.starts_with(chars);
+        if matched {
+            self.advance_by(chars.len());
         }
+        matched
+    }
+
+    // consumes chars if the stream starts with them, ignoring ASCII case
+    pub fn consume_ci(&mut self, chars: &str) -> bool {
+        let matched = self.starts_with_ci(chars);
+        if matched {
+            self.advance_by(chars.len());
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(input: &str) -> Stream {
+        let mut stream = Stream::new();
+        stream.push_str(input);
+        stream.close();
+        stream
+    }
+
+    // offset, line and column
+    fn at(stream: &Stream) -> (usize, usize, usize) {
+        let position = stream.position();
+        (position.offset, position.line, position.column)
+    }
+
+    #[test]
+    fn lines_end_at_cr_lf_and_crlf() {
+        let mut stream = stream("a\r\nb\rc\nd");
+        let mut positions = vec![at(&stream)];
+        while !stream.is_eof() {
+            stream.advance();
+            positions.push(at(&stream));
+        }
+        assert_eq!(positions, vec![
+            (0, 1, 1),
+            (1, 1, 2),
+            (3, 2, 1),
+            (4, 2, 2),
+            (5, 3, 1),
+            (6, 3, 2),
+            (7, 4, 1),
+            (8, 4, 2),
+        ]);
+        assert_eq!(stream.position_at(3), Position { offset: 3, line: 2, column: 1 });
+    }
+
+    #[test]
+    fn columns_count_code_points() {
+        let mut stream = stream("\u{E9}\u{1F600}x");
+        stream.advance();
+        assert_eq!(at(&stream), (2, 1, 2));
+        stream.advance();
+        assert_eq!(at(&stream), (6, 1, 3));
+        assert_eq!(stream.position_at(6), stream.position());
+    }
+
+    #[test]
+    fn advance_by_never_stops_inside_a_code_point() {
+        let mut stream = stream("\u{1F600}x");
+        stream.advance_by(3);
+        assert_eq!(at(&stream), (0, 1, 1));
+        stream.advance_by(5);
+        assert_eq!(at(&stream), (5, 1, 3));
+    }
+
+    #[test]
+    fn reconsume_steps_back_one_character() {
+        let mut stream = stream("a\r\n\u{1F600}b");
+        stream.advance();
+        stream.advance();
+        assert_eq!(at(&stream), (3, 2, 1));
+        stream.reconsume();
+        assert_eq!(at(&stream), (1, 1, 2));
+        assert_eq!(stream.current(), '\n');
+        stream.advance();
+        stream.advance();
+        stream.reconsume();
+        assert_eq!(at(&stream), (3, 2, 1));
+        assert_eq!(stream.current(), '\u{1F600}');
+        // after moving several characters at once it has to work the
+        // position out again
+        stream.advance_by(5);
+        stream.reconsume();
+        assert_eq!(at(&stream), (7, 2, 2));
+    }
+
+    // every character the stream hands out until EOF
+    fn drain(stream: &mut Stream) -> String {
+        let mut chars = String::new();
+        while !stream.is_eof() {
+            chars.push(stream.current());
+            stream.advance();
+        }
+        chars
+    }
+
+    #[test]
+    fn newlines_are_normalized() {
+        let mut normalized = stream("a\r\nb\rc\r\r\nd\n");
+        assert_eq!(drain(&mut normalized), "a\nb\nc\n\nd\n");
+        assert_eq!(normalized.position().line, 6);
+        let peeked = stream("\r\n\rx");
+        assert_eq!(peeked.peek(1), Some('\n'));
+        assert_eq!(peeked.peek(2), Some('x'));
+        assert_eq!(peeked.peek(3), None);
+    }
+
+    #[test]
+    fn crlf_split_across_chunks() {
+        let mut stream = Stream::new();
+        stream.push_str("a\r");
+        stream.advance();
+        assert_eq!(stream.current(), '\n');
+        stream.advance();
+        stream.push_str("\nb");
+        stream.close();
+        assert_eq!(at(&stream), (3, 2, 1));
+        assert_eq!(drain(&mut stream), "b");
+        // the CR hasn't been consumed yet, so the pair is still ahead
+        let mut stream = Stream::new();
+        stream.push_str("\r");
+        stream.push_str("\nb");
+        stream.close();
+        assert_eq!(drain(&mut stream), "\nb");
+        assert_eq!(at(&stream), (3, 2, 2));
+    }
+
+    #[test]
+    fn input_stream_errors() {
+        let mut stream = stream("a\u{1}\t\0\u{7F}\u{9F}\u{FDD0}\u{10FFFF}\u{FFFD}");
+        drain(&mut stream);
+        let errors: Vec<_> = stream.take_errors().iter().map(|error| error.present()).collect();
+        assert_eq!(errors, vec![
+            "1:2 control-character-in-input-stream",
+            "1:5 control-character-in-input-stream",
+            "1:6 control-character-in-input-stream",
+            "1:7 noncharacter-in-input-stream",
+            "1:8 noncharacter-in-input-stream",
+        ]);
+    }
+
+    #[test]
+    fn errors_are_reported_once() {
+        let mut stream = stream("\u{1}\u{FFFE}");
+        stream.advance();
+        stream.reconsume();
+        stream.advance_by(5);
+        stream.reconsume();
+        drain(&mut stream);
+        let errors: Vec<_> = stream.take_errors().iter().map(|error| error.present()).collect();
+        assert_eq!(errors, vec![
+            "1:1 control-character-in-input-stream",
+            "1:2 noncharacter-in-input-stream",
+        ]);
+    }
+
+    #[test]
+    fn case_insensitive_lookahead() {
+        let mut stream = stream("DocType \u{C9}");
+        assert!(stream.starts_with_ci("doctype"));
+        assert!(!stream.starts_with("doctype"));
+        assert!(!stream.consume_ci("doctypes"));
+        assert_eq!(stream.offset(), 0);
+        assert!(stream.consume_ci("DOCTYPE "));
+        assert_eq!(at(&stream), (8, 1, 9));
+        // only ASCII letters are folded
+        assert!(!stream.starts_with_ci("\u{E9}"));
+        assert!(stream.starts_with_ci("\u{C9}"));
+    }
 
+    #[test]
+    fn case_insensitive_lookahead_at_the_end_of_a_chunk() {
+        let mut stream = Stream::new();
+        stream.push_str("<!Doc");
+        stream.advance_by(2);
+        assert!(!stream.starts_with_ci("doctype"));
+        assert!(!stream.consume_ci("doctype"));
+        assert_eq!(stream.offset(), 2);
+        // the partial match is still there once the rest arrives
+        stream.push_str("TyPe");
+        assert!(stream.consume_ci("doctype"));
+        assert_eq!(stream.remaining(), 0);
+        // a code point cut short by the end of the data doesn't match
+        let mut stream = Stream::new();
+        stream.push_str("\u{E9}");
+        assert!(!stream.starts_with_ci("\u{E9}x"));
+        assert!(!stream.consume_ci("ab"));
     }
 
-    pub fn consume(&mut self, chars: &str) {
-        self.idx += chars.len() - 1
+    #[test]
+    fn lookahead_stops_at_the_insertion_point() {
+        let mut stream = Stream::new();
+        stream.push_str("in;");
+        stream.set_insertion_point(Some(0));
+        stream.insert_str("&not");
+        assert_eq!(stream.peek_slice(8), "&not");
+        assert!(!stream.starts_with("&notin"));
+        assert_eq!(stream.peek(4), None);
+        assert_eq!(stream.consume_until(&[';']), "&not");
+        assert!(stream.at_insertion_point());
+        stream.set_insertion_point(None);
+        assert_eq!(stream.consume_until(&[]), "in;");
     }
 }